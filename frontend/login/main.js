@@ -35,9 +35,7 @@ document.getElementById("login-button").addEventListener("click", async (event)
     });
 
     if (response.ok) {
-        const data = await response.json();
-        localStorage.setItem("jwt", data.token);   
-
+        // The session lives in the HttpOnly cookies the response set.
         window.location.href = redirect;
     } else {
         const errorMessage = await response.text();
@@ -52,7 +50,8 @@ document.getElementById("register-button").addEventListener("click", async (even
         method: "POST",
         body: JSON.stringify({
             username: document.getElementById("username").value,
-            password: document.getElementById("password").value
+            password: document.getElementById("password").value,
+            email: document.getElementById("email").value || undefined
         }),
         headers: {
             "Content-Type": "application/json"
@@ -60,18 +59,56 @@ document.getElementById("register-button").addEventListener("click", async (even
     });
 
     if (response.ok) {
-        const data = await response.json();
-        localStorage.setItem("jwt", data.token);
-
+        // The session lives in the HttpOnly cookies the response set.
         window.location.href = redirect;
     } else {
         const errorMessage = await response.text();
         document.getElementById("error-message").innerText = errorMessage;
     }
 });
+document.getElementById("forgot-password-button").addEventListener("click", async (event) => {
+    event.preventDefault();
+
+    const response = await fetch("/api/password/forgot", {
+        method: "POST",
+        body: JSON.stringify({
+            email: document.getElementById("email").value
+        }),
+        headers: {
+            "Content-Type": "application/json"
+        }
+    });
+    document.getElementById("error-message").innerText = response.ok
+        ? "If an account uses that address, a reset link is on its way."
+        : await response.text();
+});
+
 document.getElementById("continue-as-guest-button").addEventListener("click", (event) => {
     event.preventDefault();
 
-    localStorage.setItem("jwt", "guest");
+    // The docs accept the literal token `guest` in place of a session. A logged-in
+    // session cookie is HttpOnly, so this cannot replace it.
+    document.cookie = "wiki_session=guest; path=/; SameSite=Lax";
     window.location.href = pickRedirect();
+});
+
+async function logout(everywhere) {
+    // The refresh cookie identifies the session.
+    const response = await fetch(everywhere ? "/api/logout?everywhere=true" : "/api/logout", {
+        method: "POST",
+        credentials: "same-origin"
+    });
+    document.getElementById("error-message").innerText = response.ok
+        ? (everywhere ? "Logged out on every device." : "Logged out.")
+        : await response.text();
+}
+
+document.getElementById("logout-button").addEventListener("click", (event) => {
+    event.preventDefault();
+    logout(false);
+});
+
+document.getElementById("logout-everywhere-button").addEventListener("click", (event) => {
+    event.preventDefault();
+    logout(true);
 });
\ No newline at end of file
@@ -0,0 +1,23 @@
+const token = new URLSearchParams(window.location.search).get("token");
+
+document.getElementById("reset-button").addEventListener("click", async (event) => {
+    event.preventDefault();
+
+    const response = await fetch("/api/password/reset", {
+        method: "POST",
+        body: JSON.stringify({
+            token,
+            password: document.getElementById("password").value
+        }),
+        headers: {
+            "Content-Type": "application/json"
+        }
+    });
+
+    if (response.ok) {
+        // Every session ended with the reset, so start over at the login page.
+        window.location.href = "/login/";
+    } else {
+        document.getElementById("error-message").innerText = await response.text();
+    }
+});
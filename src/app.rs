@@ -1,18 +1,41 @@
 use axum::Router;
-use axum::routing::post;
+use axum::routing::{get, post};
 use tower_http::services::ServeDir;
 
-use crate::{docs::ServeDocs, user};
+use crate::docs::templates::TEMPLATES_ROOT;
+use crate::docs::{DOCS_ROOT, ServeDocs, api, search, tags};
+use crate::mail::Mailer;
+use crate::user::password;
+use crate::{CONFIG, MAILER, user};
 
 pub fn router() -> Router {
+    router_with_mailer(&MAILER)
+}
+
+/// The app with mail, such as password reset links, sent through `mailer` rather than
+/// [`MAILER`], so tests can read what was sent.
+pub fn router_with_mailer(mailer: &'static Mailer) -> Router {
     let api_routes = Router::new()
         .route("/api/login", post(user::login_handler))
-        .route("/api/register", post(user::register_handler));
+        .route("/api/register", post(user::register_handler))
+        .route("/api/refresh", post(user::refresh_handler))
+        .route("/api/logout", post(user::logout_handler))
+        .route("/api/password/forgot", post(password::forgot_handler))
+        .route("/api/password/reset", post(password::reset_handler))
+        .route("/api/search", get(search::search_handler))
+        .route("/api/tags", get(tags::tags_handler))
+        .route("/api/docs/{*page}", get(api::page_handler))
+        .with_state(mailer);
 
     let static_files = ServeDir::new("frontend");
 
     Router::new()
         .merge(api_routes)
-        .nest_service("/docs", ServeDocs::new("docs"))
+        .nest_service(
+            "/docs",
+            ServeDocs::new(DOCS_ROOT)
+                .with_markdown_extensions(CONFIG.markdown.clone())
+                .with_templates(TEMPLATES_ROOT),
+        )
         .fallback_service(static_files)
 }
@@ -0,0 +1,98 @@
+use rusqlite::{Connection, Result, params};
+
+use super::Requirement;
+
+/// The link graph between pages. `required_level` and `required_roles` are the gates
+/// around the link, so a backlink is only revealed to readers who could see the link
+/// itself.
+pub(super) const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS page_links (
+    source TEXT NOT NULL,
+    target TEXT NOT NULL,
+    required_level INTEGER NOT NULL,
+    required_roles TEXT NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS page_links_source ON page_links (source);
+CREATE INDEX IF NOT EXISTS page_links_target ON page_links (target);";
+
+/// A source page and the `(requirement, target)` links found in it.
+pub type PageLinks = (String, Vec<(Requirement, String)>);
+
+/// Adds `required_roles` to link graphs recorded before roles existed.
+pub(super) fn migrate(conn: &Connection) -> Result<()> {
+    let has_roles: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('page_links') WHERE name = 'required_roles')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_roles {
+        conn.execute_batch(
+            "ALTER TABLE page_links ADD COLUMN required_roles TEXT NOT NULL DEFAULT ''",
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_links(conn: &Connection, source: &str, links: &[(Requirement, String)]) -> Result<()> {
+    conn.execute("DELETE FROM page_links WHERE source = ?1", params![source])?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO page_links (source, target, required_level, required_roles)
+         VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for (requirement, target) in links {
+        stmt.execute(params![
+            source,
+            target,
+            requirement.level,
+            requirement.roles_column()
+        ])?;
+    }
+    Ok(())
+}
+
+pub(super) fn replace_links(
+    conn: &Connection,
+    source: &str,
+    links: &[(Requirement, String)],
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    insert_links(&tx, source, links)?;
+    tx.commit()
+}
+
+/// Replaces the whole graph, dropping links from pages that no longer exist.
+pub(super) fn replace_all_links(conn: &Connection, pages: &[PageLinks]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM page_links", [])?;
+    for (source, links) in pages {
+        insert_links(&tx, source, links)?;
+    }
+    tx.commit()
+}
+
+/// Pages linking to `target` from a section a reader at `permissions` holding `roles` may
+/// read, sorted by name.
+pub(super) fn backlinks(
+    conn: &Connection,
+    target: &str,
+    permissions: i32,
+    roles: &[String],
+) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT source, required_level, required_roles FROM page_links
+         WHERE target = ?1 AND source != ?1 AND (?2 = 0 OR required_level <= ?2)
+         ORDER BY source",
+    )?;
+    let rows = stmt.query_map(params![target, permissions], |row| {
+        let requirement = Requirement::from_columns(row.get(1)?, &row.get::<_, String>(2)?);
+        Ok((row.get::<_, String>(0)?, requirement))
+    })?;
+
+    let mut sources: Vec<String> = Vec::new();
+    for row in rows {
+        let (source, requirement) = row?;
+        if requirement.allows(permissions, roles) && sources.last() != Some(&source) {
+            sources.push(source);
+        }
+    }
+    Ok(sources)
+}
@@ -14,7 +14,21 @@ use argon2::{Argon2, PasswordHasher, PasswordVerifier};
 use rusqlite::{Connection, Error as RusqliteError, Result, params};
 use std::sync::OnceLock;
 use tokio::sync::{mpsc, oneshot};
-use tokio::task;
+
+mod links;
+mod password_resets;
+mod revisions;
+mod roles;
+mod search;
+mod sessions;
+mod tags;
+
+pub use links::PageLinks;
+pub use revisions::Revision;
+pub use roles::{ROOT_ROLE, Requirement, is_valid_role, level_role};
+pub use search::{IndexedPage, MATCH_END, MATCH_START, SearchHit};
+pub use sessions::Rotation;
+pub use tags::PageTags;
 
 #[derive(Debug)]
 pub enum DbRequest {
@@ -22,6 +36,7 @@ pub enum DbRequest {
         username: String,
         password_hash: String,
         privileges: i32,
+        email: Option<String>,
         resp: oneshot::Sender<Result<()>>,
     },
     SetUserPrivileges {
@@ -34,6 +49,122 @@ pub enum DbRequest {
         password: String,
         resp: oneshot::Sender<Result<LoginResult>>,
     },
+    AddRevision {
+        page: String,
+        author: String,
+        summary: String,
+        content: String,
+        resp: oneshot::Sender<Result<i64>>,
+    },
+    ListRevisions {
+        page: String,
+        resp: oneshot::Sender<Result<Vec<Revision>>>,
+    },
+    GetRevision {
+        id: i64,
+        resp: oneshot::Sender<Result<Option<Revision>>>,
+    },
+    IndexPage {
+        page: String,
+        sections: Vec<(Requirement, String)>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    ReplaceIndex {
+        pages: Vec<IndexedPage>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    ReplaceLinks {
+        source: String,
+        links: Vec<(Requirement, String)>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    ReplaceAllLinks {
+        pages: Vec<PageLinks>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    Backlinks {
+        target: String,
+        permissions: i32,
+        roles: Vec<String>,
+        resp: oneshot::Sender<Result<Vec<String>>>,
+    },
+    Search {
+        query: String,
+        permissions: i32,
+        roles: Vec<String>,
+        limit: usize,
+        resp: oneshot::Sender<Result<Vec<SearchHit>>>,
+    },
+    ReplaceTags {
+        page: String,
+        tags: Vec<String>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    ReplaceAllTags {
+        pages: Vec<PageTags>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    TaggedPages {
+        permissions: i32,
+        roles: Vec<String>,
+        resp: oneshot::Sender<Result<Vec<(String, String)>>>,
+    },
+    PagesWithTag {
+        tag: String,
+        permissions: i32,
+        roles: Vec<String>,
+        resp: oneshot::Sender<Result<Vec<String>>>,
+    },
+    GrantRole {
+        username: String,
+        role: String,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    RevokeRole {
+        username: String,
+        role: String,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    UserRoles {
+        username: String,
+        resp: oneshot::Sender<Result<Vec<String>>>,
+    },
+    CreateSession {
+        username: String,
+        token_hash: String,
+        expires_at: i64,
+        resp: oneshot::Sender<Result<i64>>,
+    },
+    RotateSession {
+        token_hash: String,
+        new_hash: String,
+        expires_at: i64,
+        resp: oneshot::Sender<Result<Rotation>>,
+    },
+    EndSession {
+        token_hash: String,
+        resp: oneshot::Sender<Result<Option<String>>>,
+    },
+    EndAllSessions {
+        username: String,
+        resp: oneshot::Sender<Result<usize>>,
+    },
+    SetEmail {
+        username: String,
+        email: Option<String>,
+        resp: oneshot::Sender<Result<bool>>,
+    },
+    CreatePasswordReset {
+        email: String,
+        token_hash: String,
+        expires_at: i64,
+        resp: oneshot::Sender<Result<Option<String>>>,
+    },
+    ResetPassword {
+        token_hash: String,
+        password_hash: String,
+        resp: oneshot::Sender<Result<Option<String>>>,
+    },
     Close,
 }
 
@@ -52,9 +183,13 @@ impl Database {
         let (tx, mut rx) = mpsc::channel::<DbRequest>(32);
         let db_path = db_path.to_string();
 
-        let _ = task::spawn_blocking(move || {
+        std::thread::spawn(move || {
             println!("Database connection opening...");
             let conn = Connection::open(&db_path).expect("Failed to open DB");
+            // Another connection may be migrating the same file; wait for it instead of
+            // failing with "database is locked".
+            conn.busy_timeout(std::time::Duration::from_secs(5))
+                .expect("Failed to set busy timeout");
             conn.execute(
                 "CREATE TABLE IF NOT EXISTS users (
                     id INTEGER PRIMARY KEY,
@@ -63,112 +198,299 @@ impl Database {
                     privileges INTEGER NOT NULL,
                     privileges_last_updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
                     patreon_id TEXT,
-                    patreon_refresh_token TEXT
+                    patreon_refresh_token TEXT,
+                    email TEXT
                 )",
                 [],
             )
             .expect("Failed to create users table");
+            conn.execute_batch(revisions::SCHEMA)
+                .expect("Failed to create revisions table");
+            conn.execute_batch(roles::SCHEMA)
+                .expect("Failed to create roles tables");
+            roles::migrate_levels(&conn).expect("Failed to map privilege levels onto roles");
+            search::migrate(&conn).expect("Failed to migrate search index");
+            conn.execute_batch(search::SCHEMA)
+                .expect("Failed to create search index");
+            conn.execute_batch(links::SCHEMA)
+                .expect("Failed to create page_links table");
+            links::migrate(&conn).expect("Failed to migrate page_links table");
+            conn.execute_batch(tags::SCHEMA)
+                .expect("Failed to create page_tags table");
+            conn.execute_batch(sessions::SCHEMA)
+                .expect("Failed to create sessions table");
+            password_resets::migrate(&conn).expect("Failed to add email addresses to users");
+            conn.execute_batch(password_resets::SCHEMA)
+                .expect("Failed to create password_resets table");
 
-            let rt = tokio::runtime::Handle::current();
-            rt.block_on(async move {
-                while let Some(req) = rx.recv().await {
-                    match req {
-                        DbRequest::Close => {
-                            rx.close();
-
-                            println!("Database connection closed.");
-                            break;
-                        }
-                        DbRequest::AddUser { username, password_hash, privileges, resp } => {
-                            let result = conn.execute(
-                                "INSERT INTO users (username, password, privileges) VALUES (?1, ?2, ?3)",
-                                params![username, password_hash, privileges],
-                            ).map(|_| ());
-                            let _ = resp.send(result);
-                        }
-                        DbRequest::SetUserPrivileges { user_id, privileges, resp } => {
-                            let result = conn
-                                .execute(
-                                    "UPDATE users SET privileges = ?1, privileges_last_updated = CURRENT_TIMESTAMP WHERE id = ?2",
-                                    params![privileges, user_id],
-                                )
-                                .map(|_| ());
-                            let _ = resp.send(result);
-                        }
-                        DbRequest::Login { username, password, resp } => {
-                            use chrono::{Duration, NaiveDateTime, Utc};
-
-                            let result: Result<LoginResult> = (|| {
-                                let mut stmt = conn.prepare(
-                                    "SELECT password, privileges, privileges_last_updated, id, patreon_id, patreon_refresh_token FROM users WHERE username = ?1",
-                                )?;
-
-                                let mut rows = stmt.query(params![username])?;
-                                match rows.next()? {
-                                    Some(row) => {
-                                        let stored_password: String = row.get(0)?;
-                                        let parsed_hash = match PasswordHash::new(&stored_password) {
-                                            Ok(hash) => hash,
-                                            Err(_) => return Ok(LoginResult::Privileges(None)),
-                                        };
-
-                                        if Argon2::default()
-                                            .verify_password(password.as_bytes(), &parsed_hash)
-                                            .is_err()
-                                        {
-                                            return Ok(LoginResult::Privileges(None));
-                                        }
+            while let Some(req) = rx.blocking_recv() {
+                match req {
+                    DbRequest::Close => {
+                        rx.close();
+
+                        println!("Database connection closed.");
+                        break;
+                    }
+                    DbRequest::AddUser {
+                        username,
+                        password_hash,
+                        privileges,
+                        email,
+                        resp,
+                    } => {
+                        let result: Result<()> = (|| {
+                            let tx = conn.unchecked_transaction()?;
+                            tx.execute(
+                                "INSERT INTO users (username, password, privileges, email) VALUES (?1, ?2, ?3, ?4)",
+                                params![username, password_hash, privileges, email],
+                            )?;
+                            let user_id = tx.last_insert_rowid() as i32;
+                            roles::grant_by_id(&tx, user_id, &level_role(privileges))?;
+                            tx.commit()
+                        })();
+                        let _ = resp.send(result);
+                    }
+                    DbRequest::SetUserPrivileges {
+                        user_id,
+                        privileges,
+                        resp,
+                    } => {
+                        let _ = resp.send(roles::set_level(&conn, user_id, privileges));
+                    }
+                    DbRequest::AddRevision {
+                        page,
+                        author,
+                        summary,
+                        content,
+                        resp,
+                    } => {
+                        let _ =
+                            resp.send(revisions::insert(&conn, &page, &author, &summary, &content));
+                    }
+                    DbRequest::ListRevisions { page, resp } => {
+                        let _ = resp.send(revisions::list(&conn, &page));
+                    }
+                    DbRequest::GetRevision { id, resp } => {
+                        let _ = resp.send(revisions::get(&conn, id));
+                    }
+                    DbRequest::IndexPage {
+                        page,
+                        sections,
+                        resp,
+                    } => {
+                        let _ = resp.send(search::index_page(&conn, &page, &sections));
+                    }
+                    DbRequest::ReplaceIndex { pages, resp } => {
+                        let _ = resp.send(search::replace_index(&conn, &pages));
+                    }
+                    DbRequest::ReplaceLinks {
+                        source,
+                        links,
+                        resp,
+                    } => {
+                        let _ = resp.send(links::replace_links(&conn, &source, &links));
+                    }
+                    DbRequest::ReplaceAllLinks { pages, resp } => {
+                        let _ = resp.send(links::replace_all_links(&conn, &pages));
+                    }
+                    DbRequest::Backlinks {
+                        target,
+                        permissions,
+                        roles,
+                        resp,
+                    } => {
+                        let _ = resp.send(links::backlinks(&conn, &target, permissions, &roles));
+                    }
+                    DbRequest::Search {
+                        query,
+                        permissions,
+                        roles,
+                        limit,
+                        resp,
+                    } => {
+                        let _ =
+                            resp.send(search::search(&conn, &query, permissions, &roles, limit));
+                    }
+                    DbRequest::ReplaceTags { page, tags, resp } => {
+                        let _ = resp.send(tags::replace_tags(&conn, &page, &tags));
+                    }
+                    DbRequest::ReplaceAllTags { pages, resp } => {
+                        let _ = resp.send(tags::replace_all_tags(&conn, &pages));
+                    }
+                    DbRequest::TaggedPages {
+                        permissions,
+                        roles,
+                        resp,
+                    } => {
+                        let _ = resp.send(tags::tagged_pages(&conn, permissions, &roles));
+                    }
+                    DbRequest::PagesWithTag {
+                        tag,
+                        permissions,
+                        roles,
+                        resp,
+                    } => {
+                        let _ = resp.send(tags::pages_with_tag(&conn, &tag, permissions, &roles));
+                    }
+                    DbRequest::GrantRole {
+                        username,
+                        role,
+                        resp,
+                    } => {
+                        let _ = resp.send(roles::grant(&conn, &username, &role));
+                    }
+                    DbRequest::RevokeRole {
+                        username,
+                        role,
+                        resp,
+                    } => {
+                        let _ = resp.send(roles::revoke(&conn, &username, &role));
+                    }
+                    DbRequest::UserRoles { username, resp } => {
+                        let _ = resp.send(roles::user_roles(&conn, &username));
+                    }
+                    DbRequest::CreateSession {
+                        username,
+                        token_hash,
+                        expires_at,
+                        resp,
+                    } => {
+                        let _ =
+                            resp.send(sessions::create(&conn, &username, &token_hash, expires_at));
+                    }
+                    DbRequest::RotateSession {
+                        token_hash,
+                        new_hash,
+                        expires_at,
+                        resp,
+                    } => {
+                        let _ =
+                            resp.send(sessions::rotate(&conn, &token_hash, &new_hash, expires_at));
+                    }
+                    DbRequest::EndSession { token_hash, resp } => {
+                        let _ = resp.send(sessions::end(&conn, &token_hash));
+                    }
+                    DbRequest::EndAllSessions { username, resp } => {
+                        let _ = resp.send(sessions::end_all(&conn, &username));
+                    }
+                    DbRequest::SetEmail {
+                        username,
+                        email,
+                        resp,
+                    } => {
+                        let _ = resp.send(password_resets::set_email(
+                            &conn,
+                            &username,
+                            email.as_deref(),
+                        ));
+                    }
+                    DbRequest::CreatePasswordReset {
+                        email,
+                        token_hash,
+                        expires_at,
+                        resp,
+                    } => {
+                        let _ = resp.send(password_resets::create(
+                            &conn,
+                            &email,
+                            &token_hash,
+                            expires_at,
+                        ));
+                    }
+                    DbRequest::ResetPassword {
+                        token_hash,
+                        password_hash,
+                        resp,
+                    } => {
+                        let _ =
+                            resp.send(password_resets::redeem(&conn, &token_hash, &password_hash));
+                    }
+                    DbRequest::Login {
+                        username,
+                        password,
+                        resp,
+                    } => {
+                        use chrono::{Duration, NaiveDateTime, Utc};
 
-                                        let privileges: i32 = row.get(1)?;
-                                        let last_updated_str: String = row.get(2)?;
-                                        let user_id: i32 = row.get(3)?;
-                                        let patreon_id: Option<String> = row.get(4)?;
-                                        let patreon_refresh_token: Option<String> = row.get(5)?;
-
-                                        let needs_verify = if privileges != 0 && privileges != 1 {
-                                            if let Ok(last_updated) = NaiveDateTime::parse_from_str(
-                                                &last_updated_str,
-                                                "%Y-%m-%d %H:%M:%S",
-                                            ) {
-                                                let now = Utc::now().naive_utc();
-                                                now.signed_duration_since(last_updated) > Duration::days(30)
-                                            } else {
-                                                false
-                                            }
+                        let result: Result<LoginResult> = (|| {
+                            let mut stmt = conn.prepare(
+                                "SELECT password, privileges, privileges_last_updated, id, patreon_id, patreon_refresh_token FROM users WHERE username = ?1",
+                            )?;
+
+                            let mut rows = stmt.query(params![username])?;
+                            match rows.next()? {
+                                Some(row) => {
+                                    let stored_password: String = row.get(0)?;
+                                    let parsed_hash = match PasswordHash::new(&stored_password) {
+                                        Ok(hash) => hash,
+                                        Err(_) => return Ok(LoginResult::Privileges(None)),
+                                    };
+
+                                    if Argon2::default()
+                                        .verify_password(password.as_bytes(), &parsed_hash)
+                                        .is_err()
+                                    {
+                                        return Ok(LoginResult::Privileges(None));
+                                    }
+
+                                    let privileges: i32 = row.get(1)?;
+                                    let last_updated_str: String = row.get(2)?;
+                                    let user_id: i32 = row.get(3)?;
+                                    let patreon_id: Option<String> = row.get(4)?;
+                                    let patreon_refresh_token: Option<String> = row.get(5)?;
+
+                                    let needs_verify = if privileges != 0 && privileges != 1 {
+                                        if let Ok(last_updated) = NaiveDateTime::parse_from_str(
+                                            &last_updated_str,
+                                            "%Y-%m-%d %H:%M:%S",
+                                        ) {
+                                            let now = Utc::now().naive_utc();
+                                            now.signed_duration_since(last_updated)
+                                                > Duration::days(30)
                                         } else {
                                             false
-                                        };
-
-                                        if needs_verify {
-                                            Ok(LoginResult::NeedsVerification {
-                                                privileges,
-                                                user_id,
-                                                patreon_id,
-                                                patreon_refresh_token,
-                                            })
-                                        } else {
-                                            Ok(LoginResult::Privileges(Some(privileges)))
                                         }
+                                    } else {
+                                        false
+                                    };
+
+                                    if needs_verify {
+                                        Ok(LoginResult::NeedsVerification {
+                                            privileges,
+                                            user_id,
+                                            patreon_id,
+                                            patreon_refresh_token,
+                                        })
+                                    } else {
+                                        Ok(LoginResult::Privileges(Some(privileges)))
                                     }
-                                    None => Ok(LoginResult::Privileges(None)),
                                 }
-                            })();
+                                None => Ok(LoginResult::Privileges(None)),
+                            }
+                        })();
 
-                            let _ = resp.send(result);
-                        }
+                        let _ = resp.send(result);
                     }
                 }
-            });
+            }
         });
         Ok(Database { tx })
     }
 
     pub async fn add_user(&self, username: &str, password: &str, privileges: i32) -> Result<()> {
-        let salt = SaltString::generate(&mut OsRng);
-        let password_hash = Argon2::default()
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|_| RusqliteError::InvalidQuery)?
-            .to_string();
+        self.add_user_with_email(username, password, privileges, None)
+            .await
+    }
+
+    /// Adds a user whose password reset links go to `email`, which no other user may have.
+    pub async fn add_user_with_email(
+        &self,
+        username: &str,
+        password: &str,
+        privileges: i32,
+        email: Option<&str>,
+    ) -> Result<()> {
+        let password_hash = hash_password(password)?;
 
         let (resp_tx, resp_rx) = oneshot::channel();
 
@@ -176,6 +498,7 @@ impl Database {
             username: username.to_string(),
             password_hash,
             privileges,
+            email: email.map(str::to_string),
             resp: resp_tx,
         };
 
@@ -229,6 +552,464 @@ impl Database {
             }
         }
     }
+
+    /// Records a new revision of `page` and returns its id.
+    pub async fn add_revision(
+        &self,
+        page: &str,
+        author: &str,
+        summary: &str,
+        content: &str,
+    ) -> Result<i64> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::AddRevision {
+            page: page.to_string(),
+            author: author.to_string(),
+            summary: summary.to_string(),
+            content: content.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send AddRevision request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Lists every revision of `page`, newest first.
+    pub async fn revisions(&self, page: &str) -> Result<Vec<Revision>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ListRevisions {
+            page: page.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ListRevisions request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    pub async fn revision(&self, id: i64) -> Result<Option<Revision>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::GetRevision { id, resp: resp_tx };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send GetRevision request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Replaces the search index entries of `page` with `sections`, given as
+    /// `(requirement, text)` pairs.
+    pub async fn index_page(&self, page: &str, sections: Vec<(Requirement, String)>) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::IndexPage {
+            page: page.to_string(),
+            sections,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send IndexPage request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Rebuilds the search index from scratch.
+    pub async fn replace_index(&self, pages: Vec<IndexedPage>) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ReplaceIndex {
+            pages,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ReplaceIndex request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Replaces the outgoing links recorded for `source`, given as
+    /// `(requirement, target)` pairs.
+    pub async fn replace_links(
+        &self,
+        source: &str,
+        links: Vec<(Requirement, String)>,
+    ) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ReplaceLinks {
+            source: source.to_string(),
+            links,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ReplaceLinks request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Rebuilds the link graph from scratch.
+    pub async fn replace_all_links(&self, pages: Vec<PageLinks>) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ReplaceAllLinks {
+            pages,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ReplaceAllLinks request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Pages linking to `target` from sections readable at `permissions` with `roles`.
+    pub async fn backlinks(
+        &self,
+        target: &str,
+        permissions: i32,
+        roles: &[String],
+    ) -> Result<Vec<String>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::Backlinks {
+            target: target.to_string(),
+            permissions,
+            roles: roles.to_vec(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send Backlinks request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Full-text search over sections readable at `permissions` with `roles`, one hit per
+    /// page.
+    pub async fn search(
+        &self,
+        query: &str,
+        permissions: i32,
+        roles: &[String],
+        limit: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::Search {
+            query: query.to_string(),
+            permissions,
+            roles: roles.to_vec(),
+            limit,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send Search request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Replaces the tags recorded for `page`.
+    pub async fn replace_tags(&self, page: &str, tags: Vec<String>) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ReplaceTags {
+            page: page.to_string(),
+            tags,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ReplaceTags request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Rebuilds every page's tags from scratch.
+    pub async fn replace_all_tags(&self, pages: Vec<PageTags>) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ReplaceAllTags {
+            pages,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ReplaceAllTags request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Every `(tag, page)` pair on a page with some section readable at `permissions` with
+    /// `roles`, sorted by tag and then page.
+    pub async fn tagged_pages(
+        &self,
+        permissions: i32,
+        roles: &[String],
+    ) -> Result<Vec<(String, String)>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::TaggedPages {
+            permissions,
+            roles: roles.to_vec(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send TaggedPages request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Pages tagged `tag` with some section readable at `permissions` with `roles`, sorted
+    /// by name.
+    pub async fn pages_with_tag(
+        &self,
+        tag: &str,
+        permissions: i32,
+        roles: &[String],
+    ) -> Result<Vec<String>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::PagesWithTag {
+            tag: tag.to_string(),
+            permissions,
+            roles: roles.to_vec(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send PagesWithTag request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Gives `username` the role `role`, creating the role if needed.
+    pub async fn grant_role(&self, username: &str, role: &str) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::GrantRole {
+            username: username.to_string(),
+            role: role.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send GrantRole request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    pub async fn revoke_role(&self, username: &str, role: &str) -> Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::RevokeRole {
+            username: username.to_string(),
+            role: role.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send RevokeRole request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// The roles `username` holds, sorted by name.
+    pub async fn user_roles(&self, username: &str) -> Result<Vec<String>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::UserRoles {
+            username: username.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send UserRoles request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Opens a session for `username` whose refresh token hashes to `token_hash`, valid
+    /// until the Unix time `expires_at`, and returns its id.
+    pub async fn create_session(
+        &self,
+        username: &str,
+        token_hash: &str,
+        expires_at: i64,
+    ) -> Result<i64> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::CreateSession {
+            username: username.to_string(),
+            token_hash: token_hash.to_string(),
+            expires_at,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send CreateSession request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Exchanges the refresh token hashing to `token_hash` for the one hashing to
+    /// `new_hash`, extending the session until `expires_at`. Presenting a token that was
+    /// already exchanged ends its session.
+    pub async fn rotate_session(
+        &self,
+        token_hash: &str,
+        new_hash: &str,
+        expires_at: i64,
+    ) -> Result<Rotation> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::RotateSession {
+            token_hash: token_hash.to_string(),
+            new_hash: new_hash.to_string(),
+            expires_at,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send RotateSession request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Ends the session holding `token_hash`, returning its user if there was one.
+    pub async fn end_session(&self, token_hash: &str) -> Result<Option<String>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::EndSession {
+            token_hash: token_hash.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send EndSession request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Ends every session of `username`, returning how many were open.
+    pub async fn end_all_sessions(&self, username: &str) -> Result<usize> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::EndAllSessions {
+            username: username.to_string(),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send EndAllSessions request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Sets the address password reset links for `username` are mailed to, returning
+    /// whether there is such a user. Addresses are unique across users.
+    pub async fn set_email(&self, username: &str, email: Option<&str>) -> Result<bool> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::SetEmail {
+            username: username.to_string(),
+            email: email.map(str::to_string),
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send SetEmail request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Issues a password reset whose token hashes to `token_hash`, valid until the Unix
+    /// time `expires_at`, to the user registered with `email`, and returns their name. Any
+    /// earlier reset of theirs stops working.
+    pub async fn create_password_reset(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: i64,
+    ) -> Result<Option<String>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::CreatePasswordReset {
+            email: email.to_string(),
+            token_hash: token_hash.to_string(),
+            expires_at,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send CreatePasswordReset request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+
+    /// Sets a new password with the reset token hashing to `token_hash`, which only works
+    /// once, and ends the user's sessions. Returns whose password changed.
+    pub async fn reset_password(
+        &self,
+        token_hash: &str,
+        new_password: &str,
+    ) -> Result<Option<String>> {
+        let password_hash = hash_password(new_password)?;
+
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let req = DbRequest::ResetPassword {
+            token_hash: token_hash.to_string(),
+            password_hash,
+            resp: resp_tx,
+        };
+
+        self.tx
+            .send(req)
+            .await
+            .expect("Failed to send ResetPassword request");
+
+        resp_rx.await.expect("DB thread panicked")
+    }
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| RusqliteError::InvalidQuery)?
+        .to_string())
 }
 
 fn verify_privilege(
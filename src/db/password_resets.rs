@@ -0,0 +1,105 @@
+use rusqlite::{Connection, OptionalExtension, Result, params};
+
+/// One row per outstanding reset link. Only hashes of the tokens are stored.
+pub(super) const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS password_resets (
+    token_hash TEXT PRIMARY KEY,
+    username TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS password_resets_username ON password_resets (username);";
+
+/// Gives databases created before password resets the `users.email` column, and makes
+/// addresses unique so a reset link can only go to one account.
+pub(super) fn migrate(conn: &Connection) -> Result<()> {
+    let has_email: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('users') WHERE name = 'email')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_email {
+        conn.execute_batch("ALTER TABLE users ADD COLUMN email TEXT")?;
+    }
+    conn.execute_batch("CREATE UNIQUE INDEX IF NOT EXISTS users_email ON users (email)")
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Returns whether `username` exists.
+pub(super) fn set_email(conn: &Connection, username: &str, email: Option<&str>) -> Result<bool> {
+    conn.execute(
+        "UPDATE users SET email = ?1 WHERE username = ?2",
+        params![email, username],
+    )
+    .map(|changed| changed > 0)
+}
+
+/// Replaces any outstanding reset of the account registered to `email` with one for
+/// `token_hash`, and returns the account's name.
+pub(super) fn create(
+    conn: &Connection,
+    email: &str,
+    token_hash: &str,
+    expires_at: i64,
+) -> Result<Option<String>> {
+    let tx = conn.unchecked_transaction()?;
+    let username: Option<String> = tx
+        .query_row(
+            "SELECT username FROM users WHERE email = ?1",
+            params![email],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(username) = username else {
+        return Ok(None);
+    };
+
+    tx.execute(
+        "DELETE FROM password_resets WHERE username = ?1 OR expires_at <= ?2",
+        params![username, now()],
+    )?;
+    tx.execute(
+        "INSERT INTO password_resets (token_hash, username, expires_at) VALUES (?1, ?2, ?3)",
+        params![token_hash, username, expires_at],
+    )?;
+    tx.commit()?;
+    Ok(Some(username))
+}
+
+/// Uses up the live reset holding `token_hash`, sets its account's password to
+/// `password_hash` and ends the account's sessions. Returns whose password changed.
+pub(super) fn redeem(
+    conn: &Connection,
+    token_hash: &str,
+    password_hash: &str,
+) -> Result<Option<String>> {
+    let tx = conn.unchecked_transaction()?;
+    let username: Option<String> = tx
+        .query_row(
+            "DELETE FROM password_resets WHERE token_hash = ?1 AND expires_at > ?2
+             RETURNING username",
+            params![token_hash, now()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(username) = username else {
+        return Ok(None);
+    };
+
+    tx.execute(
+        "UPDATE users SET password = ?1 WHERE username = ?2",
+        params![password_hash, username],
+    )?;
+    tx.execute(
+        "DELETE FROM password_resets WHERE username = ?1",
+        params![username],
+    )?;
+    tx.execute(
+        "DELETE FROM sessions WHERE username = ?1",
+        params![username],
+    )?;
+    tx.commit()?;
+    Ok(Some(username))
+}
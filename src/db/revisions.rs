@@ -0,0 +1,64 @@
+use rusqlite::{Connection, OptionalExtension, Result, Row, params};
+
+pub(super) const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS revisions (
+    id INTEGER PRIMARY KEY,
+    page TEXT NOT NULL,
+    author TEXT NOT NULL,
+    summary TEXT NOT NULL DEFAULT '',
+    content TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS revisions_page ON revisions (page, id);";
+
+/// A saved version of a page. `page` is the docs-relative path without the `.md` suffix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub id: i64,
+    pub page: String,
+    pub author: String,
+    pub summary: String,
+    pub content: String,
+    pub created_at: String,
+}
+
+fn from_row(row: &Row) -> Result<Revision> {
+    Ok(Revision {
+        id: row.get(0)?,
+        page: row.get(1)?,
+        author: row.get(2)?,
+        summary: row.get(3)?,
+        content: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+pub(super) fn insert(
+    conn: &Connection,
+    page: &str,
+    author: &str,
+    summary: &str,
+    content: &str,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO revisions (page, author, summary, content) VALUES (?1, ?2, ?3, ?4)",
+        params![page, author, summary, content],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Newest first.
+pub(super) fn list(conn: &Connection, page: &str) -> Result<Vec<Revision>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, page, author, summary, content, created_at FROM revisions WHERE page = ?1 ORDER BY id DESC",
+    )?;
+    stmt.query_map(params![page], from_row)?.collect()
+}
+
+pub(super) fn get(conn: &Connection, id: i64) -> Result<Option<Revision>> {
+    conn.query_row(
+        "SELECT id, page, author, summary, content, created_at FROM revisions WHERE id = ?1",
+        params![id],
+        from_row,
+    )
+    .optional()
+}
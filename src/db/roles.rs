@@ -0,0 +1,192 @@
+use rusqlite::{Connection, Result, Transaction, TransactionBehavior, params};
+
+/// Named roles and who holds them. Pages gate content on a role with `!@name`.
+pub(super) const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS roles (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);";
+
+/// Holders of this role read everything, like privilege level 0.
+pub const ROOT_ROLE: &str = "root";
+
+/// The role a privilege level maps onto: `root` for 0, `level-N` otherwise.
+pub fn level_role(privileges: i32) -> String {
+    if privileges == 0 {
+        ROOT_ROLE.to_string()
+    } else {
+        format!("level-{}", privileges)
+    }
+}
+
+/// Role names are lowercase words so they can sit in gate markers and in the
+/// space-separated lists stored alongside indexed content.
+pub fn is_valid_role(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// What a reader needs to see a piece of content: at least `level`, and every role in
+/// `roles`. Level 0 with no roles is public.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Requirement {
+    pub level: i32,
+    pub roles: Vec<String>,
+}
+
+impl Requirement {
+    pub fn role(name: &str) -> Self {
+        Requirement {
+            level: 0,
+            roles: vec![name.to_string()],
+        }
+    }
+
+    /// The requirement of content nested inside both `self` and `other`.
+    pub fn and(&self, other: &Requirement) -> Requirement {
+        let mut roles = self.roles.clone();
+        for role in &other.roles {
+            if !roles.contains(role) {
+                roles.push(role.clone());
+            }
+        }
+        Requirement {
+            level: self.level.max(other.level),
+            roles,
+        }
+    }
+
+    /// Whether a reader at `permissions` holding `roles` may see the content. Root sees
+    /// everything.
+    pub fn allows(&self, permissions: i32, roles: &[String]) -> bool {
+        permissions == 0
+            || (permissions >= self.level && self.roles.iter().all(|role| roles.contains(role)))
+    }
+
+    /// `roles` as stored in the database.
+    pub(super) fn roles_column(&self) -> String {
+        self.roles.join(" ")
+    }
+
+    pub(super) fn from_columns(level: i32, roles: &str) -> Self {
+        Requirement {
+            level,
+            roles: roles.split_whitespace().map(String::from).collect(),
+        }
+    }
+}
+
+impl From<i32> for Requirement {
+    fn from(level: i32) -> Self {
+        Requirement {
+            level,
+            roles: Vec::new(),
+        }
+    }
+}
+
+/// One-off migration giving every existing user the role matching their privilege level.
+/// Runs once per database, tracked with `PRAGMA user_version`, so roles revoked later
+/// stay revoked.
+pub(super) fn migrate_levels(conn: &Connection) -> Result<()> {
+    // Take the write lock up front: two connections upgrading read transactions would
+    // deadlock, and the version must be re-read under the lock anyway.
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let version: i32 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version >= 1 {
+        return Ok(());
+    }
+
+    let users: Vec<(i32, i32)> = {
+        let mut stmt = tx.prepare("SELECT id, privileges FROM users")?;
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?
+    };
+    for (user_id, privileges) in users {
+        grant_by_id(&tx, user_id, &level_role(privileges))?;
+    }
+    tx.execute_batch("PRAGMA user_version = 1")?;
+    tx.commit()
+}
+
+fn role_id(conn: &Connection, name: &str) -> Result<i64> {
+    conn.execute(
+        "INSERT OR IGNORE INTO roles (name) VALUES (?1)",
+        params![name],
+    )?;
+    conn.query_row(
+        "SELECT id FROM roles WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    )
+}
+
+pub(super) fn grant_by_id(conn: &Connection, user_id: i32, role: &str) -> Result<()> {
+    let role_id = role_id(conn, role)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO user_roles (user_id, role_id) VALUES (?1, ?2)",
+        params![user_id, role_id],
+    )?;
+    Ok(())
+}
+
+/// Sets a user's privilege level and swaps the `root`/`level-N` role it maps onto for the
+/// new one, leaving other roles alone.
+pub(super) fn set_level(conn: &Connection, user_id: i32, privileges: i32) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE users SET privileges = ?1, privileges_last_updated = CURRENT_TIMESTAMP WHERE id = ?2",
+        params![privileges, user_id],
+    )?;
+    tx.execute(
+        "DELETE FROM user_roles
+         WHERE user_id = ?1
+           AND role_id IN (SELECT id FROM roles WHERE name = ?2 OR name GLOB 'level-[0-9]*')",
+        params![user_id, ROOT_ROLE],
+    )?;
+    grant_by_id(&tx, user_id, &level_role(privileges))?;
+    tx.commit()
+}
+
+fn user_id(conn: &Connection, username: &str) -> Result<i32> {
+    conn.query_row(
+        "SELECT id FROM users WHERE username = ?1",
+        params![username],
+        |row| row.get(0),
+    )
+}
+
+pub(super) fn grant(conn: &Connection, username: &str, role: &str) -> Result<()> {
+    if !is_valid_role(role) {
+        return Err(rusqlite::Error::InvalidQuery);
+    }
+    grant_by_id(conn, user_id(conn, username)?, role)
+}
+
+pub(super) fn revoke(conn: &Connection, username: &str, role: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM user_roles
+         WHERE user_id = ?1 AND role_id = (SELECT id FROM roles WHERE name = ?2)",
+        params![user_id(conn, username)?, role],
+    )?;
+    Ok(())
+}
+
+/// The roles `username` holds, sorted by name.
+pub(super) fn user_roles(conn: &Connection, username: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT roles.name FROM roles
+         JOIN user_roles ON user_roles.role_id = roles.id
+         JOIN users ON users.id = user_roles.user_id
+         WHERE users.username = ?1
+         ORDER BY roles.name",
+    )?;
+    stmt.query_map(params![username], |row| row.get(0))?
+        .collect()
+}
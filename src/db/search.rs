@@ -0,0 +1,136 @@
+use rusqlite::{Connection, Result, params};
+
+use super::Requirement;
+
+/// One row per privilege-gated section so results can be filtered by the reader's level
+/// and roles.
+pub(super) const SCHEMA: &str = "CREATE VIRTUAL TABLE IF NOT EXISTS docs_fts USING fts5 (
+    page UNINDEXED,
+    required_level UNINDEXED,
+    required_roles UNINDEXED,
+    body
+);";
+
+/// Drops an index built before sections could require roles. It is rebuilt from the docs
+/// directory on startup.
+pub(super) fn migrate(conn: &Connection) -> Result<()> {
+    let has_roles: bool = conn.query_row(
+        "SELECT NOT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'docs_fts')
+             OR EXISTS (SELECT 1 FROM pragma_table_info('docs_fts') WHERE name = 'required_roles')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_roles {
+        conn.execute_batch("DROP TABLE docs_fts")?;
+    }
+    Ok(())
+}
+
+/// Characters wrapped around matched terms in [`SearchHit::snippet`]. They come from the
+/// Unicode private use area so callers can escape the snippet before highlighting it.
+pub const MATCH_START: char = '\u{E000}';
+pub const MATCH_END: char = '\u{E001}';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub page: String,
+    pub snippet: String,
+}
+
+/// An indexed page: its key and `(requirement, text)` for each section.
+pub type IndexedPage = (String, Vec<(Requirement, String)>);
+
+fn insert_page(conn: &Connection, page: &str, sections: &[(Requirement, String)]) -> Result<()> {
+    conn.execute("DELETE FROM docs_fts WHERE page = ?1", params![page])?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO docs_fts (page, required_level, required_roles, body) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for (requirement, body) in sections {
+        stmt.execute(params![
+            page,
+            requirement.level,
+            requirement.roles_column(),
+            body
+        ])?;
+    }
+    Ok(())
+}
+
+pub(super) fn index_page(
+    conn: &Connection,
+    page: &str,
+    sections: &[(Requirement, String)],
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    insert_page(&tx, page, sections)?;
+    tx.commit()
+}
+
+/// Replaces the whole index, dropping pages that no longer exist.
+pub(super) fn replace_index(conn: &Connection, pages: &[IndexedPage]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM docs_fts", [])?;
+    for (page, sections) in pages {
+        insert_page(&tx, page, sections)?;
+    }
+    tx.commit()
+}
+
+/// Turns free text into an FTS5 query that matches every word, quoting each one so user
+/// input can never be parsed as query syntax.
+fn to_match_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Best-ranked match per page among the sections a reader at `permissions` holding `roles`
+/// may read.
+pub(super) fn search(
+    conn: &Connection,
+    query: &str,
+    permissions: i32,
+    roles: &[String],
+    limit: usize,
+) -> Result<Vec<SearchHit>> {
+    let Some(match_query) = to_match_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT page, snippet(docs_fts, 3, ?3, ?4, '…', 12), required_level, required_roles
+         FROM docs_fts
+         WHERE docs_fts MATCH ?1 AND (?2 = 0 OR required_level <= ?2)
+         ORDER BY bm25(docs_fts)",
+    )?;
+    let rows = stmt.query_map(
+        params![
+            match_query,
+            permissions,
+            MATCH_START.to_string(),
+            MATCH_END.to_string()
+        ],
+        |row| {
+            let hit = SearchHit {
+                page: row.get(0)?,
+                snippet: row.get(1)?,
+            };
+            let requirement = Requirement::from_columns(row.get(2)?, &row.get::<_, String>(3)?);
+            Ok((hit, requirement))
+        },
+    )?;
+
+    let mut hits: Vec<SearchHit> = Vec::new();
+    for row in rows {
+        let (hit, requirement) = row?;
+        if requirement.allows(permissions, roles) && hits.iter().all(|seen| seen.page != hit.page) {
+            hits.push(hit);
+            if hits.len() == limit {
+                break;
+            }
+        }
+    }
+    Ok(hits)
+}
@@ -0,0 +1,118 @@
+use rusqlite::{Connection, OptionalExtension, Result, params};
+
+/// One row per logged-in device. Only hashes of refresh tokens are stored; the previous
+/// token of each session is kept so a replayed one can be recognized.
+pub(super) const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    username TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    previous_hash TEXT,
+    expires_at INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS sessions_username ON sessions (username);
+CREATE INDEX IF NOT EXISTS sessions_previous_hash ON sessions (previous_hash);";
+
+/// What presenting a refresh token did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rotation {
+    /// The session now answers to the new token. Carries the session's id and the
+    /// user's current privileges.
+    Rotated {
+        session: i64,
+        username: String,
+        privileges: i32,
+    },
+    /// The token had already been exchanged, so someone kept a copy of it. The session
+    /// has been ended.
+    Reused,
+    /// No live session has this token.
+    Invalid,
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Returns the new session's id.
+pub(super) fn create(
+    conn: &Connection,
+    username: &str,
+    token_hash: &str,
+    expires_at: i64,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO sessions (username, token_hash, expires_at) VALUES (?1, ?2, ?3)",
+        params![username, token_hash, expires_at],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Swaps the session holding `token_hash` over to `new_hash`.
+pub(super) fn rotate(
+    conn: &Connection,
+    token_hash: &str,
+    new_hash: &str,
+    expires_at: i64,
+) -> Result<Rotation> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM sessions WHERE expires_at <= ?1",
+        params![now()],
+    )?;
+
+    let current = tx
+        .query_row(
+            "SELECT sessions.id, sessions.username, users.privileges FROM sessions
+             JOIN users ON users.username = sessions.username
+             WHERE sessions.token_hash = ?1",
+            params![token_hash],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    let rotation = match current {
+        Some((id, username, privileges)) => {
+            tx.execute(
+                "UPDATE sessions SET token_hash = ?1, previous_hash = ?2, expires_at = ?3 WHERE id = ?4",
+                params![new_hash, token_hash, expires_at, id],
+            )?;
+            Rotation::Rotated {
+                session: id,
+                username,
+                privileges,
+            }
+        }
+        None => {
+            let replayed = tx.execute(
+                "DELETE FROM sessions WHERE previous_hash = ?1",
+                params![token_hash],
+            )?;
+            if replayed > 0 {
+                Rotation::Reused
+            } else {
+                Rotation::Invalid
+            }
+        }
+    };
+    tx.commit()?;
+    Ok(rotation)
+}
+
+/// Ends the live session holding `token_hash` and returns whose it was.
+pub(super) fn end(conn: &Connection, token_hash: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "DELETE FROM sessions WHERE token_hash = ?1 AND expires_at > ?2 RETURNING username",
+        params![token_hash, now()],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Ends every session of `username` and returns how many there were.
+pub(super) fn end_all(conn: &Connection, username: &str) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM sessions WHERE username = ?1",
+        params![username],
+    )
+}
@@ -0,0 +1,98 @@
+use rusqlite::{Connection, Result, params};
+use std::collections::HashSet;
+
+use super::Requirement;
+
+/// Tags declared in each page's front matter.
+pub(super) const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS page_tags (
+    page TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (page, tag)
+);
+CREATE INDEX IF NOT EXISTS page_tags_tag ON page_tags (tag);";
+
+/// A page and its tags.
+pub type PageTags = (String, Vec<String>);
+
+fn insert_tags(conn: &Connection, page: &str, tags: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM page_tags WHERE page = ?1", params![page])?;
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO page_tags (page, tag) VALUES (?1, ?2)")?;
+    for tag in tags {
+        stmt.execute(params![page, tag])?;
+    }
+    Ok(())
+}
+
+pub(super) fn replace_tags(conn: &Connection, page: &str, tags: &[String]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    insert_tags(&tx, page, tags)?;
+    tx.commit()
+}
+
+/// Replaces every page's tags, dropping pages that no longer exist.
+pub(super) fn replace_all_tags(conn: &Connection, pages: &[PageTags]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM page_tags", [])?;
+    for (page, tags) in pages {
+        insert_tags(&tx, page, tags)?;
+    }
+    tx.commit()
+}
+
+/// Pages whose every section in the search index is gated above a reader at `permissions`
+/// holding `roles`. Pages with no indexed text have nothing to hide.
+fn unreadable_pages(
+    conn: &Connection,
+    permissions: i32,
+    roles: &[String],
+) -> Result<HashSet<String>> {
+    if permissions == 0 {
+        return Ok(HashSet::new());
+    }
+
+    let mut stmt = conn.prepare("SELECT page, required_level, required_roles FROM docs_fts")?;
+    let rows = stmt.query_map([], |row| {
+        let requirement = Requirement::from_columns(row.get(1)?, &row.get::<_, String>(2)?);
+        Ok((row.get::<_, String>(0)?, requirement))
+    })?;
+
+    let mut indexed = HashSet::new();
+    let mut readable = HashSet::new();
+    for row in rows {
+        let (page, requirement) = row?;
+        if requirement.allows(permissions, roles) {
+            readable.insert(page.clone());
+        }
+        indexed.insert(page);
+    }
+    Ok(indexed.difference(&readable).cloned().collect())
+}
+
+/// Every `(tag, page)` pair on a page a reader at `permissions` holding `roles` may read
+/// some of, sorted by tag and then page.
+pub(super) fn tagged_pages(
+    conn: &Connection,
+    permissions: i32,
+    roles: &[String],
+) -> Result<Vec<(String, String)>> {
+    let hidden = unreadable_pages(conn, permissions, roles)?;
+    let mut stmt = conn.prepare("SELECT tag, page FROM page_tags ORDER BY tag, page")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.filter(|row| !matches!(row, Ok((_, page)) if hidden.contains(page)))
+        .collect()
+}
+
+/// Pages tagged `tag` that a reader at `permissions` holding `roles` may read some of,
+/// sorted by name.
+pub(super) fn pages_with_tag(
+    conn: &Connection,
+    tag: &str,
+    permissions: i32,
+    roles: &[String],
+) -> Result<Vec<String>> {
+    let hidden = unreadable_pages(conn, permissions, roles)?;
+    let mut stmt = conn.prepare("SELECT page FROM page_tags WHERE tag = ?1 ORDER BY page")?;
+    let rows = stmt.query_map(params![tag], |row| row.get::<_, String>(0))?;
+    rows.filter(|row| !matches!(row, Ok(page) if hidden.contains(page)))
+        .collect()
+}
@@ -0,0 +1,92 @@
+use axum::Json;
+use axum::extract::Path;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+use super::front_matter::{self, FrontMatter};
+use super::{Caller, DOCS_ROOT, backlinks::readable_sources, links, paths, policy};
+
+#[derive(Serialize)]
+struct Backlink {
+    page: String,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct PageInfo {
+    page: String,
+    url: String,
+    #[serde(flatten)]
+    front_matter: FrontMatter,
+}
+
+/// `GET /api/docs/<page>` for a page's front matter, and `GET /api/docs/<page>/...` for
+/// other metadata that lives outside the rendered page.
+pub async fn page_handler(headers: HeaderMap, Path(rest): Path<String>) -> Response {
+    let caller = Caller::from_headers(&headers).unwrap_or_else(Caller::guest);
+
+    let (page, wants_backlinks) = match rest.strip_suffix("/backlinks") {
+        Some(page) => (page, true),
+        None => (rest.as_str(), false),
+    };
+    // `Path` has already percent-decoded the page.
+    let Some(page) = paths::normalize(page).filter(|page| !page.is_empty()) else {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    let page = page.as_str();
+    if !policy::readable(DOCS_ROOT, page, &caller).await {
+        return (
+            StatusCode::FORBIDDEN,
+            "This page requires higher privileges",
+        )
+            .into_response();
+    }
+
+    if wants_backlinks {
+        backlinks(page, &caller).await
+    } else {
+        page_info(page).await
+    }
+}
+
+/// `GET /api/docs/<page>`
+async fn page_info(page: &str) -> Response {
+    let Some(path) = paths::page_file(DOCS_ROOT, page).await else {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    let Ok(doc) = tokio::fs::read_to_string(&path).await else {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+
+    let info = PageInfo {
+        page: page.to_string(),
+        url: links::page_url(page),
+        front_matter: front_matter::parse_or_default(page, &doc),
+    };
+    (StatusCode::OK, Json(info)).into_response()
+}
+
+/// `GET /api/docs/<page>/backlinks`
+async fn backlinks(page: &str, caller: &Caller) -> Response {
+    match readable_sources(&crate::DB, DOCS_ROOT, page, caller).await {
+        Ok(sources) => {
+            let backlinks: Vec<Backlink> = sources
+                .into_iter()
+                .map(|source| Backlink {
+                    url: links::page_url(&source),
+                    page: source,
+                })
+                .collect();
+            (StatusCode::OK, Json(backlinks)).into_response()
+        }
+        Err(err) => {
+            eprintln!("Failed to load backlinks of {}: {}", page, err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to load backlinks",
+            )
+                .into_response()
+        }
+    }
+}
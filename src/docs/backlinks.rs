@@ -0,0 +1,94 @@
+use super::markdown::outgoing_links;
+use super::{Caller, RenderContext, escape_html, links, policy};
+use crate::config::MarkdownExtensions;
+use crate::db::{Database, PageLinks, Requirement};
+
+/// The outgoing links of `doc`. Wiki links resolve by existence alone here; who may
+/// follow them is checked when the graph is read.
+async fn page_links(
+    root: &str,
+    doc: &str,
+    extensions: &MarkdownExtensions,
+) -> Vec<(Requirement, String)> {
+    let context = RenderContext {
+        links: Some(links::load(root, &[doc], extensions, None).await),
+        extensions: extensions.clone(),
+        ..RenderContext::default()
+    };
+    outgoing_links(doc, &context)
+}
+
+/// Records the outgoing links of `page` in the link graph.
+pub async fn index_document(
+    database: &Database,
+    root: &str,
+    page: &str,
+    doc: &str,
+    extensions: &MarkdownExtensions,
+) -> rusqlite::Result<()> {
+    database
+        .replace_links(page, page_links(root, doc, extensions).await)
+        .await
+}
+
+/// Rebuilds the link graph from `(page, markdown)` pairs.
+pub async fn replace_all(
+    database: &Database,
+    root: &str,
+    pages: &[(String, String)],
+    extensions: &MarkdownExtensions,
+) -> rusqlite::Result<()> {
+    let mut links: Vec<PageLinks> = Vec::with_capacity(pages.len());
+    for (page, doc) in pages {
+        links.push((page.clone(), page_links(root, doc, extensions).await));
+    }
+    database.replace_all_links(links).await
+}
+
+/// Pages linking to `page` that `caller` may read, both the link and the page itself.
+pub async fn readable_sources(
+    database: &Database,
+    root: &str,
+    page: &str,
+    caller: &Caller,
+) -> rusqlite::Result<Vec<String>> {
+    let sources = database
+        .backlinks(page, caller.permissions, &caller.roles)
+        .await?;
+    let mut readable = Vec::with_capacity(sources.len());
+    for source in sources {
+        if policy::readable(root, &source, caller).await {
+            readable.push(source);
+        }
+    }
+    Ok(readable)
+}
+
+/// The "Linked from" panel shown under a page, or nothing when no readable page links here.
+pub async fn panel(database: &Database, root: &str, page: &str, caller: &Caller) -> String {
+    let sources = match readable_sources(database, root, page, caller).await {
+        Ok(sources) => sources,
+        Err(err) => {
+            eprintln!("Failed to load backlinks of {}: {}", page, err);
+            return String::new();
+        }
+    };
+    if sources.is_empty() {
+        return String::new();
+    }
+
+    let items: String = sources
+        .iter()
+        .map(|source| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape_html(&links::page_url(source)),
+                escape_html(source)
+            )
+        })
+        .collect();
+    format!(
+        "<aside class=\"backlinks\"><h2>Linked from</h2><ul>{}</ul></aside>",
+        items
+    )
+}
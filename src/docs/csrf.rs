@@ -0,0 +1,114 @@
+//! CSRF tokens for the forms [`ServeDocs`](super::ServeDocs) renders.
+//!
+//! A token is an HMAC of the caller's name and login session, so nothing is stored, a new
+//! login gets a new token and one session's token is useless to another. Only callers
+//! identified by the session cookie need one: browsers attach cookies to forged cross-site
+//! requests, but a page on another site cannot make them send an `Authorization` header.
+
+use axum::body::{Body, to_bytes};
+use axum::http::{Method, Request, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, crypto};
+
+use super::Caller;
+use crate::KEYS;
+
+/// The form field carrying the token.
+pub const FIELD: &str = "csrf_token";
+
+/// The header carrying the token, for scripts that post JSON with the session cookie.
+pub const HEADER: &str = "X-CSRF-Token";
+
+/// Form bodies are buffered to find the token; anything larger is refused.
+const MAX_FORM_BYTES: usize = 2 * 1024 * 1024;
+
+fn message(caller: &Caller) -> String {
+    let session = caller.session.map(|id| id.to_string()).unwrap_or_default();
+    format!("csrf:{}:{}", caller.username, session)
+}
+
+/// The token `caller`'s forms must send back.
+pub fn token(caller: &Caller) -> String {
+    crypto::sign(
+        message(caller).as_bytes(),
+        &EncodingKey::from_secret(KEYS.hmac_secret()),
+        Algorithm::HS256,
+    )
+    .expect("HMAC signing does not fail")
+}
+
+/// Whether `presented` is `caller`'s token, compared in constant time.
+pub fn verify(caller: &Caller, presented: &str) -> bool {
+    crypto::verify(
+        presented,
+        message(caller).as_bytes(),
+        &DecodingKey::from_secret(KEYS.hmac_secret()),
+        Algorithm::HS256,
+    )
+    .unwrap_or(false)
+}
+
+/// A hidden input carrying `caller`'s token, for embedding in a POST form.
+pub fn hidden_input(caller: &Caller) -> String {
+    format!(
+        "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+        FIELD,
+        token(caller)
+    )
+}
+
+/// Lets `req` through if it cannot change anything, was not authenticated by cookie, or
+/// carries `caller`'s token in the [`HEADER`] or a form body's [`FIELD`]. A buffered form
+/// body is handed on intact. Anything else is answered with `403 Forbidden`.
+pub(super) async fn check(req: Request<Body>, caller: &Caller) -> Result<Request<Body>, Response> {
+    if !matches!(*req.method(), Method::POST | Method::PUT | Method::DELETE) || !caller.via_cookie {
+        return Ok(req);
+    }
+
+    if let Some(presented) = req.headers().get(HEADER) {
+        let presented = presented.to_str().unwrap_or("");
+        return if verify(caller, presented) {
+            Ok(req)
+        } else {
+            Err(rejected())
+        };
+    }
+
+    let is_form = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false);
+    if !is_form {
+        return Err(rejected());
+    }
+
+    let (parts, body) = req.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_FORM_BYTES).await else {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response());
+    };
+    let presented = form_field(&bytes, FIELD).unwrap_or_default();
+    if verify(caller, &presented) {
+        Ok(Request::from_parts(parts, Body::from(bytes)))
+    } else {
+        Err(rejected())
+    }
+}
+
+/// The first value of `name` in a form-urlencoded body.
+fn form_field(body: &[u8], name: &str) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    body.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| {
+            urlencoding::decode(&value.replace('+', " "))
+                .map(|value| value.into_owned())
+                .unwrap_or_default()
+        })
+    })
+}
+
+fn rejected() -> Response {
+    (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response()
+}
@@ -0,0 +1,131 @@
+use similar::{Algorithm, DiffOp, capture_diff_slices_deadline};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/// How long one diff may search for the shortest edit script before settling for a
+/// coarser one. Revisions are user content, so their size is not ours to bound.
+const DIFF_DEADLINE: Duration = Duration::from_millis(500);
+
+/// Line-based diff. Uses Myers' algorithm in linear space, so large revisions cost time
+/// in proportion to how much changed rather than memory in proportion to their product.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    for (old_index, new_index) in matching_lines(&old, &new) {
+        lines.extend(old[i..old_index].iter().map(|line| DiffLine::Removed(line)));
+        lines.extend(new[j..new_index].iter().map(|line| DiffLine::Added(line)));
+        lines.push(DiffLine::Same(old[old_index]));
+        i = old_index + 1;
+        j = new_index + 1;
+    }
+    lines.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    lines.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    lines
+}
+
+/// Index pairs `(old, new)` of the lines both sides keep, in order.
+fn matching_lines(old: &[&str], new: &[&str]) -> Vec<(usize, usize)> {
+    let deadline = Instant::now() + DIFF_DEADLINE;
+    capture_diff_slices_deadline(Algorithm::Myers, old, new, Some(deadline))
+        .into_iter()
+        .filter_map(|op| match op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => Some((0..len).map(move |k| (old_index + k, new_index + k))),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+pub struct Merge {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/// Three-way line merge of `ours` and `theirs`, both derived from `base`. Regions changed
+/// on only one side are taken from that side; regions changed differently on both sides
+/// are wrapped in git-style conflict markers.
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> Merge {
+    let base: Vec<&str> = base.lines().collect();
+    let ours: Vec<&str> = ours.lines().collect();
+    let theirs: Vec<&str> = theirs.lines().collect();
+
+    let mut in_ours = vec![None; base.len()];
+    for (b, o) in matching_lines(&base, &ours) {
+        in_ours[b] = Some(o);
+    }
+    let mut in_theirs = vec![None; base.len()];
+    for (b, t) in matching_lines(&base, &theirs) {
+        in_theirs[b] = Some(t);
+    }
+
+    // Base lines that survived on both sides anchor the merge; everything between two
+    // anchors is a chunk that may have changed. A sentinel anchor closes the last chunk.
+    let anchors = (0..base.len())
+        .filter_map(|b| Some((b, in_ours[b]?, in_theirs[b]?)))
+        .chain(std::iter::once((base.len(), ours.len(), theirs.len())));
+
+    let mut merged: Vec<&str> = Vec::new();
+    let mut conflicts = 0;
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    for (anchor_b, anchor_o, anchor_t) in anchors {
+        let base_chunk = &base[b..anchor_b];
+        let our_chunk = &ours[o..anchor_o];
+        let their_chunk = &theirs[t..anchor_t];
+
+        if our_chunk == their_chunk || their_chunk == base_chunk {
+            merged.extend_from_slice(our_chunk);
+        } else if our_chunk == base_chunk {
+            merged.extend_from_slice(their_chunk);
+        } else {
+            conflicts += 1;
+            merged.push("<<<<<<< yours");
+            merged.extend_from_slice(our_chunk);
+            merged.push("=======");
+            merged.extend_from_slice(their_chunk);
+            merged.push(">>>>>>> theirs");
+        }
+
+        if anchor_b < base.len() {
+            merged.push(base[anchor_b]);
+        }
+        (b, o, t) = (anchor_b + 1, anchor_o + 1, anchor_t + 1);
+    }
+
+    let mut text = merged.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Merge { text, conflicts }
+}
+
+pub fn render_diff(lines: &[DiffLine]) -> String {
+    let mut html = String::from("<pre class=\"diff\">");
+    for line in lines {
+        let (class, sign, text) = match line {
+            DiffLine::Same(text) => ("diff-same", ' ', text),
+            DiffLine::Added(text) => ("diff-added", '+', text),
+            DiffLine::Removed(text) => ("diff-removed", '-', text),
+        };
+        html.push_str(&format!(
+            "<span class=\"{}\">{} {}</span>\n",
+            class,
+            sign,
+            super::escape_html(text)
+        ));
+    }
+    html.push_str("</pre>");
+    html
+}
@@ -0,0 +1,405 @@
+use axum::body::Body;
+use axum::extract::{Form, FromRequest, Json};
+use axum::http::{Request, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+
+use super::diff::{diff_lines, merge3, render_diff};
+use super::{Caller, csrf, escape_html, paths, render_page, templates};
+use crate::config::MarkdownExtensions;
+use crate::db::Database;
+
+/// Serializes the compare-and-write step of every save so two editors cannot both pass the
+/// staleness check before either has written.
+pub(super) static WRITE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Author of revisions recording changes made to page files directly on disk.
+const OUTSIDE_AUTHOR: &str = "(outside the wiki)";
+
+#[derive(Deserialize)]
+pub struct SaveRequest {
+    content: String,
+    #[serde(default)]
+    summary: String,
+    /// [`content_hash`] of the page the editor started from, or of the empty string for a
+    /// page that does not exist yet. Saves without one are refused with `428`.
+    #[serde(default)]
+    base: Option<String>,
+    /// The template a new page's editor was seeded from. Only then are its placeholders
+    /// filled in.
+    #[serde(default)]
+    template: String,
+}
+
+#[derive(Serialize)]
+struct SaveResponse {
+    path: String,
+    revision: i64,
+}
+
+#[derive(Serialize)]
+struct ConflictResponse {
+    error: &'static str,
+    base: String,
+    current: String,
+    merged: String,
+    conflicts: usize,
+}
+
+/// Hex SHA-256 of a page's Markdown, used as the edit form's base version.
+pub fn content_hash(contents: &str) -> String {
+    Sha256::digest(contents.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn render_form(uri_path: &str, contents: &str, caller: &Caller) -> String {
+    format!(
+        "<html><body>{}</body></html>",
+        form_html(
+            uri_path,
+            contents,
+            &content_hash(contents),
+            "",
+            None,
+            caller
+        )
+    )
+}
+
+/// The editor for a page that does not exist yet, starting from `contents` (a template or
+/// nothing) and offering `templates` to start from instead.
+pub fn render_new_page_form(
+    uri_path: &str,
+    contents: &str,
+    caller: &Caller,
+    templates: &[String],
+    selected: Option<&str>,
+) -> String {
+    let picker = if templates.is_empty() {
+        String::new()
+    } else {
+        templates::picker(uri_path, templates, selected)
+    };
+    // The page on disk is still empty, whatever the template put in the editor.
+    format!(
+        "<html><body>{}{}</body></html>",
+        picker,
+        form_html(uri_path, contents, &content_hash(""), "", selected, caller)
+    )
+}
+
+fn form_html(
+    uri_path: &str,
+    contents: &str,
+    base: &str,
+    summary: &str,
+    template: Option<&str>,
+    caller: &Caller,
+) -> String {
+    let template = template
+        .map(|name| {
+            format!(
+                "<input type=\"hidden\" name=\"template\" value=\"{}\">",
+                escape_html(name)
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        "<form method=\"post\" action=\"/docs{}?edit\">{}<textarea name=\"content\" rows=\"20\" cols=\"80\">{}</textarea><br><input type=\"hidden\" name=\"base\" value=\"{}\">{}<input name=\"summary\" size=\"80\" placeholder=\"Summary of changes\" value=\"{}\"><br><button type=\"submit\">Save</button></form>",
+        escape_html(uri_path),
+        csrf::hidden_input(caller),
+        escape_html(contents),
+        base,
+        template,
+        escape_html(summary)
+    )
+}
+
+/// Handles a `POST /docs/<page>?edit`, accepting either the HTML form body or a JSON
+/// `{"content": ..., "summary": ...}` payload. Form submissions are redirected back to the
+/// rendered page.
+pub async fn save(
+    database: &Database,
+    root: &str,
+    caller: &Caller,
+    page: &str,
+    extensions: &MarkdownExtensions,
+    req: Request<Body>,
+) -> Response {
+    let uri_path = req.uri().path().to_string();
+    let Some(target) = paths::page_file(root, page).await else {
+        return (StatusCode::BAD_REQUEST, "Invalid document path").into_response();
+    };
+
+    let is_json = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/json"))
+        .unwrap_or(false);
+
+    let mut payload = if is_json {
+        match Json::<SaveRequest>::from_request(req, &()).await {
+            Ok(Json(payload)) => payload,
+            Err(rejection) => return rejection.into_response(),
+        }
+    } else {
+        match Form::<SaveRequest>::from_request(req, &()).await {
+            Ok(Form(payload)) => payload,
+            Err(rejection) => return rejection.into_response(),
+        }
+    };
+
+    // Browsers submit textareas with CRLF line endings.
+    payload.content = payload.content.replace("\r\n", "\n");
+    let Some(base) = payload.base.clone() else {
+        return (
+            StatusCode::PRECONDITION_REQUIRED,
+            "Saves must include the base version the edit started from",
+        )
+            .into_response();
+    };
+
+    let _guard = WRITE_LOCK.lock().await;
+
+    let existing = tokio::fs::read_to_string(&target).await.ok();
+    if let Some(existing) = &existing {
+        record_outside_changes(database, page, existing).await;
+    }
+    let current = existing.clone().unwrap_or_default();
+    if base != content_hash(&current) {
+        return conflict(
+            database, caller, page, &uri_path, &base, &current, &payload, is_json,
+        )
+        .await;
+    }
+    if existing.is_none() && !payload.template.is_empty() {
+        payload.content = templates::fill(
+            &payload.content,
+            page,
+            &caller.username,
+            chrono::Utc::now().date_naive(),
+        );
+    }
+
+    let revision = match store(
+        database,
+        root,
+        page,
+        &caller.username,
+        payload.summary.trim(),
+        &payload.content,
+        extensions,
+    )
+    .await
+    {
+        Ok(revision) => revision,
+        Err(response) => return response,
+    };
+
+    let location = format!("/docs{}", uri_path);
+    if is_json {
+        (
+            StatusCode::OK,
+            Json(SaveResponse {
+                path: location,
+                revision,
+            }),
+        )
+            .into_response()
+    } else {
+        Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(header::LOCATION, location)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
+
+/// Answers a stale save with `409 Conflict`, showing what changed on each side since
+/// `base` and offering a three-way merge to resolve and resubmit.
+#[allow(clippy::too_many_arguments)]
+async fn conflict(
+    database: &Database,
+    caller: &Caller,
+    page: &str,
+    uri_path: &str,
+    base: &str,
+    current: &str,
+    payload: &SaveRequest,
+    is_json: bool,
+) -> Response {
+    let submitted = payload.content.as_str();
+    // The base is only known by hash, so look it up among the recorded revisions.
+    let base_contents = database.revisions(page).await.ok().and_then(|revisions| {
+        revisions
+            .into_iter()
+            .find(|revision| content_hash(&revision.content) == base)
+            .map(|revision| revision.content)
+    });
+
+    // Without the base there is no telling which side changed what, so the whole page is
+    // left as one conflict rather than risk silently dropping either side's edits.
+    let merge = merge3(base_contents.as_deref().unwrap_or(""), submitted, current);
+    let current_base = content_hash(current);
+
+    if is_json {
+        return (
+            StatusCode::CONFLICT,
+            Json(ConflictResponse {
+                error: "conflict",
+                base: current_base,
+                current: current.to_string(),
+                merged: merge.text,
+                conflicts: merge.conflicts,
+            }),
+        )
+            .into_response();
+    }
+
+    let changes = match &base_contents {
+        Some(base_contents) => format!(
+            "<h2>Their changes</h2>{}<h2>Your changes</h2>{}",
+            render_diff(&diff_lines(base_contents, current)),
+            render_diff(&diff_lines(base_contents, submitted))
+        ),
+        None => format!(
+            "<h2>Saved version compared to yours</h2>{}",
+            render_diff(&diff_lines(current, submitted))
+        ),
+    };
+    let notice = if merge.conflicts > 0 {
+        format!(
+            "<p>{} conflicting region(s) are marked with <code>&lt;&lt;&lt;&lt;&lt;&lt;&lt; yours</code> / <code>&gt;&gt;&gt;&gt;&gt;&gt;&gt; theirs</code> below. Resolve them before saving.</p>",
+            merge.conflicts
+        )
+    } else {
+        "<p>Your changes were merged with theirs below without conflicts. Review and save again.</p>".to_string()
+    };
+
+    let html = format!(
+        "<h1>Edit conflict on <a href=\"/docs{uri}\">{page}</a></h1><p>Someone else saved this page after you started editing.</p>{changes}<h2>Merged</h2>{notice}{form}",
+        uri = escape_html(uri_path),
+        page = escape_html(page),
+        changes = changes,
+        notice = notice,
+        form = form_html(
+            uri_path,
+            &merge.text,
+            &current_base,
+            &payload.summary,
+            None,
+            caller,
+        ),
+    );
+    render_page(StatusCode::CONFLICT, &html)
+}
+
+/// Records `contents`, the page as it is on disk, as a revision unless it is the latest one
+/// already. Saves call this under [`WRITE_LOCK`] before replacing the file, so changes made
+/// outside the wiki stay in the history and can serve as the base of a merge.
+pub(super) async fn record_outside_changes(database: &Database, page: &str, contents: &str) {
+    let recorded = match database.revisions(page).await {
+        Ok(revisions) => revisions
+            .first()
+            .is_some_and(|latest| latest.content == contents),
+        Err(err) => {
+            eprintln!("Failed to load revisions of {}: {}", page, err);
+            return;
+        }
+    };
+    if !recorded
+        && let Err(err) = database
+            .add_revision(page, OUTSIDE_AUTHOR, "Changed outside the wiki", contents)
+            .await
+    {
+        eprintln!("Failed to record revision of {}: {}", page, err);
+    }
+}
+
+/// Writes `contents` to the page's file under `root`, records it as a new revision and
+/// refreshes the page's search index entries and outgoing links.
+pub async fn store(
+    database: &Database,
+    root: &str,
+    page: &str,
+    author: &str,
+    summary: &str,
+    contents: &str,
+    extensions: &MarkdownExtensions,
+) -> Result<i64, Response> {
+    let Some(target) = paths::page_file(root, page).await else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid document path").into_response());
+    };
+    if let Err(err) = write_atomically(&target, contents).await {
+        eprintln!("Failed to save {}: {}", target.display(), err);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to save document").into_response());
+    }
+
+    let revision = database
+        .add_revision(page, author, summary, contents)
+        .await
+        .map_err(|err| {
+            eprintln!("Failed to record revision of {}: {}", page, err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Document saved but its revision could not be recorded",
+            )
+                .into_response()
+        })?;
+
+    // Stale search, link or tag entries are not worth failing the save over; startup
+    // rebuilds them all.
+    if let Err(err) = super::search::index_document(database, page, contents).await {
+        eprintln!("Failed to update search index for {}: {}", page, err);
+    }
+    if let Err(err) =
+        super::backlinks::index_document(database, root, page, contents, extensions).await
+    {
+        eprintln!("Failed to update links of {}: {}", page, err);
+    }
+    if let Err(err) = super::tags::index_document(database, page, contents).await {
+        eprintln!("Failed to update tags of {}: {}", page, err);
+    }
+
+    Ok(revision)
+}
+
+/// Writes to a sibling temporary file and renames it over the target so readers never
+/// observe a half-written page.
+async fn write_atomically(target: &Path, contents: &str) -> std::io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let parent = target.parent().unwrap_or(Path::new("."));
+    tokio::fs::create_dir_all(parent).await?;
+
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let temp_path = parent.join(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, contents.as_bytes()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, target).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
@@ -0,0 +1,81 @@
+//! Page metadata in a leading front-matter block, either YAML between `---` lines or TOML
+//! between `+++` lines:
+//!
+//! ```markdown
+//! ---
+//! title: Apple pie
+//! description: Grandma's recipe, with notes.
+//! tags: [baking, dessert]
+//! ---
+//! # Apple pie
+//! ```
+//!
+//! The block is never rendered. Anyone who may read the page may read its metadata, so it
+//! is not the place for gated content.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Any other keys, passed through to the JSON API untouched.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+enum Format {
+    Yaml,
+    Toml,
+}
+
+/// Splits `doc` into its front-matter block, if any, and the Markdown after it.
+fn split(doc: &str) -> Option<(Format, &str, &str)> {
+    let (format, fence) = match doc.lines().next()?.trim_end() {
+        "---" => (Format::Yaml, "---"),
+        "+++" => (Format::Toml, "+++"),
+        _ => return None,
+    };
+
+    let after_open = doc.find('\n')? + 1;
+    let mut offset = after_open;
+    for line in doc[after_open..].split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == fence || (matches!(format, Format::Yaml) && trimmed == "...") {
+            let block = &doc[after_open..offset];
+            return Some((format, block, &doc[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// The Markdown of `doc` without its front matter.
+pub fn strip(doc: &str) -> &str {
+    split(doc).map_or(doc, |(_, _, body)| body)
+}
+
+/// Parses the front matter of `doc`. Pages without any get the default (empty) metadata;
+/// a block that does not parse is reported so the caller can decide how loud to be.
+pub fn parse(doc: &str) -> Result<FrontMatter, String> {
+    match split(doc) {
+        None => Ok(FrontMatter::default()),
+        Some((_, block, _)) if block.trim().is_empty() => Ok(FrontMatter::default()),
+        Some((Format::Yaml, block, _)) => {
+            serde_yaml_ng::from_str(block).map_err(|err| err.to_string())
+        }
+        Some((Format::Toml, block, _)) => toml::from_str(block).map_err(|err| err.to_string()),
+    }
+}
+
+/// Like [`parse`], but logs a broken block and falls back to no metadata.
+pub fn parse_or_default(page: &str, doc: &str) -> FrontMatter {
+    parse(doc).unwrap_or_else(|err| {
+        eprintln!("Invalid front matter in {}: {}", page, err);
+        FrontMatter::default()
+    })
+}
@@ -0,0 +1,266 @@
+//! Privilege gates inside a page.
+//!
+//! Block gates are marker lines:
+//!
+//! - `!N` gates what follows to level `N` until the next marker. A second `!M` replaces it
+//!   rather than nesting, which is how pages have always been written.
+//! - `!N {` opens a nested gate; everything up to the matching `!}` needs level `N` on top
+//!   of whatever encloses it.
+//! - `!end` closes every open gate and returns to public content.
+//!
+//! Wherever a gate takes a level `N` it also takes a role, written `@name`: `!@editors`
+//! shows what follows only to holders of the `editors` role, whatever their level.
+//!
+//! Inline gates wrap part of a line: `{!N only some readers see this}` or
+//! `{!@editors ...}`. A line that held nothing but hidden inline gates, such as a gated
+//! table row, is dropped entirely.
+//!
+//! Marker lines inside fenced code blocks are left alone, and root (privilege 0 or the
+//! `root` role) sees everything.
+
+use crate::db::{Requirement, is_valid_role};
+
+/// A run of Markdown between two block markers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Everything the gates enclosing the section require; content outside any gate is
+    /// public. Inline gates are still present in `markdown`.
+    pub required: Requirement,
+    pub markdown: String,
+}
+
+enum Marker {
+    /// `!N`
+    Switch(Requirement),
+    /// `!N {`
+    Open(Requirement),
+    /// `!}`
+    Close,
+    /// `!end`
+    End,
+}
+
+/// Parses a block marker line. Any line starting with `!` is a marker, except images.
+fn parse_marker(line: &str) -> Option<Marker> {
+    let rest = line.strip_prefix('!')?;
+    if rest.starts_with('[') {
+        return None;
+    }
+
+    let rest = rest.trim();
+    if rest == "end" {
+        return Some(Marker::End);
+    }
+    if rest == "}" {
+        return Some(Marker::Close);
+    }
+    match rest.strip_suffix('{') {
+        Some(gate) => Some(Marker::Open(parse_requirement(gate.trim()))),
+        None => Some(Marker::Switch(parse_requirement(rest))),
+    }
+}
+
+/// `@role`, or the leading digits of `text` as a level. Anything else means level 1.
+fn parse_requirement(text: &str) -> Requirement {
+    if let Some(role) = text.strip_prefix('@')
+        && is_valid_role(role)
+    {
+        return Requirement::role(role);
+    }
+    let digits: String = text.chars().take_while(|c| c.is_ascii_digit()).collect();
+    Requirement::from(digits.parse().unwrap_or(1))
+}
+
+pub(super) fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// Splits a page on its block markers, leaving out any front matter.
+pub fn split_sections(doc: &str) -> Vec<Section> {
+    let doc = super::front_matter::strip(doc);
+    // Open gates, innermost last. `true` marks gates opened with `!N {`.
+    let mut open: Vec<(Requirement, bool)> = Vec::new();
+    let mut sections = Vec::new();
+    let mut current = Section {
+        required: Requirement::default(),
+        markdown: String::new(),
+    };
+    let mut in_fence = false;
+
+    for line in doc.lines() {
+        let marker = if in_fence { None } else { parse_marker(line) };
+        let Some(marker) = marker else {
+            if is_fence(line) {
+                in_fence = !in_fence;
+            }
+            current.markdown.push_str(line);
+            current.markdown.push('\n');
+            continue;
+        };
+
+        match marker {
+            Marker::Switch(required) => {
+                if let Some((_, false)) = open.last() {
+                    open.pop();
+                }
+                open.push((required, false));
+            }
+            Marker::Open(required) => open.push((required, true)),
+            Marker::Close => {
+                while let Some((_, nested)) = open.pop() {
+                    if nested {
+                        break;
+                    }
+                }
+            }
+            Marker::End => open.clear(),
+        }
+
+        let required = open
+            .iter()
+            .fold(Requirement::default(), |all, (gate, _)| all.and(gate));
+        let finished = std::mem::replace(
+            &mut current,
+            Section {
+                required,
+                markdown: String::new(),
+            },
+        );
+        if !finished.markdown.is_empty() {
+            sections.push(finished);
+        }
+    }
+    if !current.markdown.is_empty() {
+        sections.push(current);
+    }
+
+    sections
+}
+
+/// An inline gate found on a line, as byte offsets into it.
+struct InlineGate {
+    start: usize,
+    end: usize,
+    required: Requirement,
+    content_start: usize,
+}
+
+/// Finds the next `{!N ...}` or `{!@role ...}` at or after `from`, matching nested braces.
+fn next_inline_gate(line: &str, from: usize) -> Option<InlineGate> {
+    let mut search = from;
+    while let Some(offset) = line[search..].find("{!") {
+        let start = search + offset;
+        let after = &line[start + 2..];
+        let gate = after.split(' ').next().unwrap_or_default();
+        let valid = match gate.strip_prefix('@') {
+            Some(role) => is_valid_role(role),
+            None => !gate.is_empty() && gate.chars().all(|c| c.is_ascii_digit()),
+        };
+        if valid && after[gate.len()..].starts_with(' ') {
+            let content_start = start + 2 + gate.len() + 1;
+            let mut depth = 1;
+            for (index, c) in line[content_start..].char_indices() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Some(InlineGate {
+                        start,
+                        end: content_start + index + 1,
+                        required: parse_requirement(gate),
+                        content_start,
+                    });
+                }
+            }
+        }
+        search = start + 2;
+    }
+    None
+}
+
+/// Applies the inline gates in `markdown` for a reader at `permissions` holding `roles`:
+/// readable gates are unwrapped, the rest removed.
+pub fn apply_inline_gates(markdown: &str, permissions: i32, roles: &[String]) -> String {
+    let mut output = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+
+    for line in markdown.lines() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        if in_fence || is_fence(line) {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        }
+
+        let mut rendered = String::with_capacity(line.len());
+        let mut hid_something = false;
+        let mut position = 0;
+        while let Some(gate) = next_inline_gate(line, position) {
+            rendered.push_str(&line[position..gate.start]);
+            if gate.required.allows(permissions, roles) {
+                rendered.push_str(&apply_inline_gates(
+                    &line[gate.content_start..gate.end - 1],
+                    permissions,
+                    roles,
+                ));
+                // Nested gates come back with a trailing newline of their own.
+                if rendered.ends_with('\n') {
+                    rendered.pop();
+                }
+            } else {
+                hid_something = true;
+            }
+            position = gate.end;
+        }
+        rendered.push_str(&line[position..]);
+
+        if hid_something && rendered.trim().is_empty() {
+            continue;
+        }
+        output.push_str(&rendered);
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Breaks `doc` into `(requirement, text)` fragments, pulling inline gates out of their
+/// sections, for indexes that must know exactly who may read each piece of text.
+pub fn gated_fragments(doc: &str) -> Vec<(Requirement, String)> {
+    let mut fragments = Vec::new();
+    for section in split_sections(doc) {
+        collect_fragments(&section.markdown, &section.required, &mut fragments);
+    }
+    fragments
+}
+
+fn collect_fragments(
+    markdown: &str,
+    required: &Requirement,
+    fragments: &mut Vec<(Requirement, String)>,
+) {
+    let mut outer = String::with_capacity(markdown.len());
+    for line in markdown.lines() {
+        let mut position = 0;
+        while let Some(gate) = next_inline_gate(line, position) {
+            outer.push_str(&line[position..gate.start]);
+            collect_fragments(
+                &line[gate.content_start..gate.end - 1],
+                &required.and(&gate.required),
+                fragments,
+            );
+            position = gate.end;
+        }
+        outer.push_str(&line[position..]);
+        outer.push('\n');
+    }
+
+    if !outer.trim().is_empty() {
+        fragments.push((required.clone(), outer));
+    }
+}
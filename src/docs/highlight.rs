@@ -0,0 +1,74 @@
+//! Server-side syntax highlighting of fenced code blocks.
+//!
+//! Code is marked up with CSS classes rather than inline colours so the page can switch
+//! between [`LIGHT_THEME`] and [`DARK_THEME`] along with the site's theme toggle, which
+//! puts a `dark` class on `<html>`.
+
+use comrak::plugins::syntect::{SyntectAdapter, SyntectAdapterBuilder};
+use std::sync::LazyLock;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassStyle, css_for_theme_with_class_style};
+
+pub const LIGHT_THEME: &str = "InspiredGitHub";
+pub const DARK_THEME: &str = "base16-ocean.dark";
+
+/// Prefixed so highlighting classes such as `comment` cannot clash with the site's own.
+const CLASS_PREFIX: &str = "hl-";
+
+static ADAPTER: LazyLock<SyntectAdapter> = LazyLock::new(|| {
+    SyntectAdapterBuilder::new()
+        .css_with_class_prefix(CLASS_PREFIX)
+        .build()
+});
+
+static STYLESHEET: LazyLock<String> = LazyLock::new(|| {
+    let themes = ThemeSet::load_defaults();
+    let css = |name: &str| {
+        css_for_theme_with_class_style(
+            &themes.themes[name],
+            ClassStyle::SpacedPrefixed {
+                prefix: CLASS_PREFIX,
+            },
+        )
+        .expect("bundled themes produce valid CSS")
+        // The adapter marks the `<pre>` rather than adding a `code` class.
+        .replace(
+            &format!(".{}code {{", CLASS_PREFIX),
+            ".syntax-highlighting {",
+        )
+    };
+
+    format!(
+        "{}\n{}",
+        css(LIGHT_THEME),
+        scope(&css(DARK_THEME), "html.dark")
+    )
+});
+
+/// The highlighter passed to comrak. Loading syntaxes takes a moment, so it is built once.
+pub fn adapter() -> &'static SyntectAdapter {
+    &ADAPTER
+}
+
+/// Styles for both themes, the dark one applying under `html.dark`.
+pub fn stylesheet() -> &'static str {
+    &STYLESHEET
+}
+
+/// Prefixes every selector in `css` with `ancestor`.
+fn scope(css: &str, ancestor: &str) -> String {
+    css.lines()
+        .map(|line| match line.strip_suffix(" {") {
+            Some(selectors) => format!(
+                "{} {{",
+                selectors
+                    .split(", ")
+                    .map(|selector| format!("{} {}", ancestor, selector))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
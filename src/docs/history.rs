@@ -0,0 +1,187 @@
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+use super::diff::{diff_lines, render_diff};
+use super::{Caller, csrf, escape_html, render_page};
+use crate::config::MarkdownExtensions;
+use crate::db::{Database, Revision};
+
+/// `GET /docs/<page>?history`
+pub async fn history(database: &Database, page: &str, uri_path: &str) -> Response {
+    let revisions = match database.revisions(page).await {
+        Ok(revisions) => revisions,
+        Err(err) => return database_error(err),
+    };
+
+    let uri = escape_html(uri_path);
+    let mut html = format!(
+        "<h1>History of <a href=\"/docs{0}\">{1}</a></h1>",
+        uri,
+        escape_html(page)
+    );
+    if revisions.is_empty() {
+        html.push_str("<p>No revisions have been recorded for this page.</p>");
+        return render_page(StatusCode::OK, &html);
+    }
+
+    html.push_str("<table class=\"history\"><tr><th>Revision</th><th>Saved</th><th>Author</th><th>Summary</th><th></th></tr>");
+    // Revisions arrive newest first, so the previous revision is the next element.
+    for (index, revision) in revisions.iter().enumerate() {
+        let compare = revisions
+            .get(index + 1)
+            .map(|previous| {
+                format!(
+                    "<a href=\"/docs{}?diff={}..{}\">diff</a> ",
+                    uri, previous.id, revision.id
+                )
+            })
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "<tr><td>#{id}</td><td>{created}</td><td>{author}</td><td>{summary}</td><td>{compare}<a href=\"/docs{uri}?revert={id}\">revert</a></td></tr>",
+            id = revision.id,
+            created = escape_html(&revision.created_at),
+            author = escape_html(&revision.author),
+            summary = escape_html(&revision.summary),
+            compare = compare,
+            uri = uri,
+        ));
+    }
+    html.push_str("</table>");
+
+    render_page(StatusCode::OK, &html)
+}
+
+/// `GET /docs/<page>?diff=<a>..<b>`
+pub async fn diff(database: &Database, page: &str, uri_path: &str, spec: &str) -> Response {
+    let Some((from, to)) = parse_range(spec) else {
+        return (StatusCode::BAD_REQUEST, "Expected ?diff=<from>..<to>").into_response();
+    };
+
+    let from = match page_revision(database, page, from).await {
+        Ok(revision) => revision,
+        Err(response) => return response,
+    };
+    let to = match page_revision(database, page, to).await {
+        Ok(revision) => revision,
+        Err(response) => return response,
+    };
+
+    let html = format!(
+        "<h1>Changes to <a href=\"/docs{uri}\">{page}</a></h1><p>Revision #{from} by {from_author} ({from_at}) &rarr; revision #{to} by {to_author} ({to_at})</p>{diff}<p><a href=\"/docs{uri}?history\">Back to history</a></p>",
+        uri = escape_html(uri_path),
+        page = escape_html(page),
+        from = from.id,
+        from_author = escape_html(&from.author),
+        from_at = escape_html(&from.created_at),
+        to = to.id,
+        to_author = escape_html(&to.author),
+        to_at = escape_html(&to.created_at),
+        diff = render_diff(&diff_lines(&from.content, &to.content)),
+    );
+
+    render_page(StatusCode::OK, &html)
+}
+
+/// `GET /docs/<page>?revert=<id>` shows what will change and asks for confirmation.
+pub async fn revert_form(
+    database: &Database,
+    root: &str,
+    caller: &Caller,
+    page: &str,
+    uri_path: &str,
+    id: &str,
+) -> Response {
+    let Ok(id) = id.parse() else {
+        return (StatusCode::BAD_REQUEST, "Invalid revision id").into_response();
+    };
+    let revision = match page_revision(database, page, id).await {
+        Ok(revision) => revision,
+        Err(response) => return response,
+    };
+    let current = match super::paths::page_file(root, page).await {
+        Some(target) => tokio::fs::read_to_string(target).await.unwrap_or_default(),
+        None => String::new(),
+    };
+
+    let html = format!(
+        "<h1>Revert <a href=\"/docs{uri}\">{page}</a> to revision #{id}</h1>{diff}<form method=\"post\" action=\"/docs{uri}?revert={id}\">{token}<button type=\"submit\">Revert</button></form>",
+        uri = escape_html(uri_path),
+        page = escape_html(page),
+        id = revision.id,
+        diff = render_diff(&diff_lines(&current, &revision.content)),
+        token = csrf::hidden_input(caller),
+    );
+
+    render_page(StatusCode::OK, &html)
+}
+
+/// `POST /docs/<page>?revert=<id>` saves the old content as a new revision.
+pub async fn revert(
+    database: &Database,
+    root: &str,
+    caller: &Caller,
+    page: &str,
+    uri_path: &str,
+    id: &str,
+    extensions: &MarkdownExtensions,
+) -> Response {
+    let Ok(id) = id.parse() else {
+        return (StatusCode::BAD_REQUEST, "Invalid revision id").into_response();
+    };
+    let revision = match page_revision(database, page, id).await {
+        Ok(revision) => revision,
+        Err(response) => return response,
+    };
+
+    let summary = format!("Reverted to revision #{}", revision.id);
+    let _guard = super::edit::WRITE_LOCK.lock().await;
+    if let Some(target) = super::paths::page_file(root, page).await
+        && let Ok(current) = tokio::fs::read_to_string(target).await
+    {
+        super::edit::record_outside_changes(database, page, &current).await;
+    }
+    if let Err(response) = super::edit::store(
+        database,
+        root,
+        page,
+        &caller.username,
+        &summary,
+        &revision.content,
+        extensions,
+    )
+    .await
+    {
+        return response;
+    }
+
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, format!("/docs{}", uri_path))
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn parse_range(spec: &str) -> Option<(i64, i64)> {
+    let (from, to) = spec.split_once("..")?;
+    Some((from.parse().ok()?, to.parse().ok()?))
+}
+
+/// Loads a revision, making sure it actually belongs to `page` so ids cannot be used to
+/// peek at other pages.
+async fn page_revision(database: &Database, page: &str, id: i64) -> Result<Revision, Response> {
+    match database.revision(id).await {
+        Ok(Some(revision)) if revision.page == page => Ok(revision),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Revision not found").into_response()),
+        Err(err) => Err(database_error(err)),
+    }
+}
+
+fn database_error(err: rusqlite::Error) -> Response {
+    eprintln!("Failed to load revisions: {}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to load revisions",
+    )
+        .into_response()
+}
@@ -0,0 +1,93 @@
+//! Transclusion: a line holding just `{{include: shared/oncall}}` is replaced by that
+//! page, rendered with its own gates for the current reader.
+//!
+//! Pages are loaded up front by [`load`], which only returns pages the reader may open,
+//! so rendering itself never touches the disk. An included page that is missing,
+//! unreadable or part of a cycle renders as an error in its place.
+
+use std::collections::HashMap;
+
+use super::gates::is_fence;
+use super::{Caller, paths, policy};
+
+/// At most this many distinct pages are pulled into one page, however they nest.
+pub const MAX_INCLUDED_PAGES: usize = 64;
+
+/// A run of Markdown, or an include directive, in the order they appear.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Markdown(String),
+    Include(String),
+}
+
+/// The page key named by an include directive line, as written.
+fn directive(line: &str) -> Option<&str> {
+    let inner = line.trim().strip_prefix("{{")?.strip_suffix("}}")?;
+    let target = inner.trim().strip_prefix("include:")?.trim();
+    (!target.is_empty()).then_some(target)
+}
+
+/// Splits `markdown` around its include directives. Directives in code fences are left
+/// alone.
+pub fn split(markdown: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_fence = false;
+
+    for line in markdown.split_inclusive('\n') {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        match directive(line).filter(|_| !in_fence) {
+            Some(target) => {
+                if !current.trim().is_empty() {
+                    parts.push(Part::Markdown(std::mem::take(&mut current)));
+                }
+                parts.push(Part::Include(target.to_string()));
+            }
+            None => current.push_str(line),
+        }
+    }
+    if !current.trim().is_empty() {
+        parts.push(Part::Markdown(current));
+    }
+    parts
+}
+
+/// Every page `doc` includes, gated or not, as normalized page keys.
+fn targets(doc: &str) -> Vec<String> {
+    split(doc)
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Include(target) => paths::normalize(&target),
+            Part::Markdown(_) => None,
+        })
+        .collect()
+}
+
+/// Loads the pages `doc` includes, and the pages those include, that `caller` may read.
+pub async fn load(root: &str, doc: &str, caller: &Caller) -> HashMap<String, String> {
+    let mut pages = HashMap::new();
+    let mut queue = targets(doc);
+    let mut seen = Vec::new();
+
+    while let Some(page) = queue.pop() {
+        if page.is_empty() || seen.contains(&page) || seen.len() >= MAX_INCLUDED_PAGES {
+            continue;
+        }
+        seen.push(page.clone());
+
+        if !policy::readable(root, &page, caller).await {
+            continue;
+        }
+        let Some(file) = paths::page_file(root, &page).await else {
+            continue;
+        };
+        if let Ok(included) = tokio::fs::read_to_string(file).await {
+            queue.extend(targets(&included));
+            pages.insert(page, included);
+        }
+    }
+
+    pages
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use super::markdown::wiki_link_names;
+use super::{Caller, paths, policy};
+use crate::config::MarkdownExtensions;
+
+/// Where a `[[Page]]` link points once resolved against the docs directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// The page key of an existing page.
+    Existing(String),
+    /// The page key a new page would be created under.
+    Missing(String),
+}
+
+impl LinkTarget {
+    pub fn url(&self) -> String {
+        match self {
+            LinkTarget::Existing(page) => page_url(page),
+            LinkTarget::Missing(page) => format!("{}?edit", page_url(page)),
+        }
+    }
+}
+
+/// The `/docs` URL of `page`, percent-encoding each path segment. Escape it with
+/// [`escape_html`](super::escape_html) before putting it in an attribute.
+pub fn page_url(page: &str) -> String {
+    format!("/docs/{}", encode_page(page))
+}
+
+pub(super) fn encode_page(page: &str) -> String {
+    page.split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Resolves a wiki link name such as `Serve Docs` or `plans/serve_docs` for `caller`.
+///
+/// The name is tried as written, then lowercased, then with spaces turned into hyphens
+/// and underscores, so `[[Serve Docs]]` finds `serve_docs.md`. Missing pages resolve to
+/// the lowercase, hyphenated form. Pages `caller` may not read resolve as missing too, so
+/// links cannot be used to probe for them; without a caller only existence counts.
+pub async fn resolve(root: &str, name: &str, caller: Option<&Caller>) -> LinkTarget {
+    let name = link_name(name);
+    let lower = name.to_lowercase();
+    let candidates = [
+        name.clone(),
+        lower.clone(),
+        name.replace(' ', "-"),
+        name.replace(' ', "_"),
+        lower.replace(' ', "-"),
+        lower.replace(' ', "_"),
+    ];
+    for candidate in candidates {
+        if exists(root, &candidate).await
+            && match caller {
+                Some(caller) => policy::readable(root, &candidate, caller).await,
+                None => true,
+            }
+        {
+            return LinkTarget::Existing(candidate);
+        }
+    }
+
+    LinkTarget::Missing(lower.replace(' ', "-"))
+}
+
+/// Resolves every `[[Page]]` link in `docs` for `caller`, keyed by the name as written.
+pub async fn load(
+    root: &str,
+    docs: &[&str],
+    extensions: &MarkdownExtensions,
+    caller: Option<&Caller>,
+) -> HashMap<String, LinkTarget> {
+    let mut targets = HashMap::new();
+    for doc in docs {
+        for name in wiki_link_names(doc, extensions) {
+            if let Entry::Vacant(entry) = targets.entry(name) {
+                let target = resolve(root, entry.key(), caller).await;
+                entry.insert(target);
+            }
+        }
+    }
+    targets
+}
+
+/// The target of the link written as `name`, out of those [`load`] resolved. Without
+/// resolved targets every link is assumed to exist.
+pub fn target(resolved: Option<&HashMap<String, LinkTarget>>, name: &str) -> LinkTarget {
+    match resolved {
+        Some(targets) => targets.get(name).cloned().unwrap_or_else(|| {
+            LinkTarget::Missing(link_name(name).to_lowercase().replace(' ', "-"))
+        }),
+        None => LinkTarget::Existing(link_name(name)),
+    }
+}
+
+/// `name` with blank, `.` and `..` segments dropped.
+fn link_name(name: &str) -> String {
+    name.trim()
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty() && *segment != "." && *segment != "..")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `page` is an existing page file, as [`paths::page_file`] finds it.
+async fn exists(root: &str, page: &str) -> bool {
+    let Some(file) = paths::page_file(root, page).await else {
+        return false;
+    };
+    tokio::fs::metadata(file)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+}
+
+/// The page key behind a `/docs/...` URL, ignoring any query or fragment.
+pub fn page_from_url(url: &str) -> Option<String> {
+    let path = url.strip_prefix("/docs/")?;
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let page = urlencoding::decode(path)
+        .ok()?
+        .trim_end_matches('/')
+        .to_string();
+    (!page.is_empty()).then_some(page)
+}
@@ -0,0 +1,246 @@
+use comrak::nodes::{AstNode, NodeValue};
+use std::collections::HashMap;
+
+use super::gates::{apply_inline_gates, gated_fragments, split_sections};
+use super::include::{self, Part};
+use super::links::{self, LinkTarget};
+use super::sanitize::sanitize;
+use super::toc::{self, HeadingAnchors};
+use super::{escape_html, highlight, math, paths};
+use crate::config::MarkdownExtensions;
+use crate::db::Requirement;
+
+/// Everything page rendering needs besides the Markdown and the reader's privileges.
+#[derive(Debug, Clone, Default)]
+pub struct RenderContext {
+    /// Where `[[Page]]` links point, by the name as written, as resolved by
+    /// [`links::load`]. Without them every link is rendered as if its page existed.
+    pub links: Option<HashMap<String, LinkTarget>>,
+    /// Put a table of contents above pages with at least this many visible headings
+    /// unless they place one themselves with `[[toc]]`.
+    pub auto_toc: Option<usize>,
+    pub extensions: MarkdownExtensions,
+    /// The key of the page being rendered, so it cannot include itself.
+    pub page: Option<String>,
+    /// The pages `{{include: ...}}` may pull in, by page key, as loaded by
+    /// [`include::load`]. Including anything else renders an error instead.
+    pub includes: HashMap<String, String>,
+}
+
+pub fn parse_markdown(doc: &str, permissions: i32) -> String {
+    render_markdown(doc, permissions, &[], &RenderContext::default())
+}
+
+/// Renders the parts of `doc` that a reader at `permissions` holding `roles` may read. See
+/// [`super::gates`] for the gate syntax.
+pub fn render_markdown(
+    doc: &str,
+    permissions: i32,
+    roles: &[String],
+    context: &RenderContext,
+) -> String {
+    let anchors = HeadingAnchors::default();
+    let mut including: Vec<String> = context.page.iter().cloned().collect();
+    let page = render_visible(doc, permissions, roles, context, &anchors, &mut including);
+
+    if page.trim().is_empty() {
+        return "Page requires higher privileges, try logging in.".into();
+    }
+
+    // Headings are only known once every visible section has been rendered.
+    let headings = anchors.into_headings();
+    let page = if page.contains(toc::PLACEHOLDER) {
+        page.replace(toc::PLACEHOLDER, &toc::render(&headings))
+    } else if context
+        .auto_toc
+        .is_some_and(|threshold| headings.len() >= threshold)
+    {
+        toc::render(&headings) + &page
+    } else {
+        page
+    };
+
+    sanitize(&page)
+}
+
+/// Renders the sections of `doc` the reader may see, expanding includes. `including`
+/// holds the pages being rendered, outermost first.
+fn render_visible(
+    doc: &str,
+    permissions: i32,
+    roles: &[String],
+    context: &RenderContext,
+    anchors: &HeadingAnchors,
+    including: &mut Vec<String>,
+) -> String {
+    let mut html = Vec::new();
+    for section in split_sections(doc) {
+        if !section.required.allows(permissions, roles) {
+            continue;
+        }
+        let markdown = apply_inline_gates(&section.markdown, permissions, roles);
+        for part in include::split(&markdown) {
+            html.push(match part {
+                Part::Markdown(markdown) => render_section(&markdown, context, anchors),
+                Part::Include(target) => {
+                    render_include(&target, permissions, roles, context, anchors, including)
+                }
+            });
+        }
+    }
+    html.join("\n")
+}
+
+/// Renders an included page with its own gates, or an error in its place.
+fn render_include(
+    target: &str,
+    permissions: i32,
+    roles: &[String],
+    context: &RenderContext,
+    anchors: &HeadingAnchors,
+    including: &mut Vec<String>,
+) -> String {
+    let Some(page) = paths::normalize(target).filter(|page| !page.is_empty()) else {
+        return include_error(&format!("Cannot include “{}”: not a page name.", target));
+    };
+    if let Some(start) = including.iter().position(|open| *open == page) {
+        let mut cycle = including[start..].to_vec();
+        cycle.push(page);
+        return include_error(&format!("Include cycle: {}.", cycle.join(" → ")));
+    }
+    // Missing and unreadable pages look the same, so includes cannot probe for pages.
+    let Some(doc) = context.includes.get(&page) else {
+        return include_error(&format!(
+            "Cannot include “{}”: no such page, or it requires higher privileges.",
+            page
+        ));
+    };
+
+    including.push(page);
+    let html = render_visible(doc, permissions, roles, context, anchors, including);
+    including.pop();
+    html
+}
+
+fn include_error(message: &str) -> String {
+    format!("<p class=\"include-error\">{}</p>\n", escape_html(message))
+}
+
+fn markdown_options(extensions: &MarkdownExtensions) -> comrak::Options<'static> {
+    let mut options = comrak::Options::default();
+    options.extension.wikilinks_title_after_pipe = true;
+    options.extension.table = extensions.table;
+    options.extension.strikethrough = extensions.strikethrough;
+    options.extension.tasklist = extensions.tasklist;
+    options.extension.autolink = extensions.autolink;
+    options.extension.footnotes = extensions.footnotes;
+    options.extension.description_lists = extensions.description_lists;
+    options.extension.superscript = extensions.superscript;
+    options.extension.math_dollars = extensions.math;
+    options.extension.math_code = extensions.math;
+    options
+}
+
+/// Parses a section, points its `[[Page]]` links at their resolved URLs and renders its
+/// math to MathML.
+fn parse_section<'a>(
+    arena: &'a comrak::Arena<AstNode<'a>>,
+    markdown: &str,
+    options: &comrak::Options,
+    context: &RenderContext,
+) -> &'a AstNode<'a> {
+    let root = comrak::parse_document(arena, markdown, options);
+    toc::mark_placeholders(root);
+
+    // `[[Page]]` arrives with the raw page name as its URL. Missing targets point at the
+    // editor, which the stylesheet picks out to show them as red links.
+    for node in root.descendants() {
+        let mut data = node.data.borrow_mut();
+        match data.value {
+            NodeValue::WikiLink(ref mut link) => {
+                link.url = links::target(context.links.as_ref(), &link.url).url();
+            }
+            NodeValue::Math(ref math) => {
+                data.value = NodeValue::Raw(math::to_mathml(&math.literal, math.display_math));
+            }
+            // ```` ```math ```` blocks, with `math_code` on.
+            NodeValue::CodeBlock(ref block)
+                if options.extension.math_code && block.info == "math" =>
+            {
+                data.value = NodeValue::Raw(math::to_mathml(&block.literal, true));
+            }
+            _ => {}
+        }
+    }
+
+    root
+}
+
+fn render_section(markdown: &str, context: &RenderContext, anchors: &HeadingAnchors) -> String {
+    let options = markdown_options(&context.extensions);
+    let arena = comrak::Arena::new();
+    let root = parse_section(&arena, markdown, &options, context);
+
+    let mut plugins = comrak::Plugins::default();
+    plugins.render.heading_adapter = Some(anchors);
+    plugins.render.codefence_syntax_highlighter = Some(highlight::adapter());
+    let mut html = Vec::new();
+    comrak::format_html_with_plugins(root, &options, &mut html, &plugins)
+        .expect("writing to a Vec cannot fail");
+    String::from_utf8(html).expect("comrak emits UTF-8")
+}
+
+/// The names of every `[[Page]]` link in `doc`, as written, whatever gates enclose them.
+pub fn wiki_link_names(doc: &str, extensions: &MarkdownExtensions) -> Vec<String> {
+    let options = markdown_options(extensions);
+    let mut names = Vec::new();
+    for (_, markdown) in gated_fragments(doc) {
+        for part in include::split(&markdown) {
+            let Part::Markdown(markdown) = part else {
+                continue;
+            };
+            let arena = comrak::Arena::new();
+            let root = comrak::parse_document(&arena, &markdown, &options);
+            for node in root.descendants() {
+                if let NodeValue::WikiLink(link) = &node.data.borrow().value
+                    && !names.contains(&link.url)
+                {
+                    names.push(link.url.clone());
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Every page `doc` links to, as `(requirement, page)` pairs where the requirement is that
+/// of the gates enclosing the link. Covers `[[Page]]` links and plain links to
+/// `/docs/...`.
+pub fn outgoing_links(doc: &str, context: &RenderContext) -> Vec<(Requirement, String)> {
+    let options = markdown_options(&context.extensions);
+    let mut found: Vec<(Requirement, String)> = Vec::new();
+
+    // Parsed without `parse_section`, since only the links matter here, not the math.
+    for (required, markdown) in gated_fragments(doc) {
+        let arena = comrak::Arena::new();
+        let root = comrak::parse_document(&arena, &markdown, &options);
+
+        for node in root.descendants() {
+            let target = match &node.data.borrow().value {
+                NodeValue::WikiLink(link) => {
+                    links::page_from_url(&links::target(context.links.as_ref(), &link.url).url())
+                }
+                NodeValue::Link(link) => links::page_from_url(&link.url),
+                _ => None,
+            };
+            if let Some(target) = target {
+                let link = (required.clone(), target);
+                if !found.contains(&link) {
+                    found.push(link);
+                }
+            }
+        }
+    }
+
+    found
+}
@@ -0,0 +1,718 @@
+//! TeX math to MathML, so `$...$` and `$$...$$` render without client-side JavaScript and
+//! stay readable wherever the HTML ends up.
+//!
+//! Covers the TeX that design docs actually use: scripts, fractions, roots, Greek letters,
+//! the common operators, relations and functions, `\text` and font commands,
+//! `\left`/`\right`, accents, spacing, and the matrix, `cases` and `aligned`
+//! environments. Unknown commands render as an error in place rather than vanishing, and
+//! the TeX source is kept as an annotation.
+
+use super::escape_html;
+
+/// How deeply groups, command arguments and environments may nest. Deeper formulas
+/// render as an error rather than recursing without bound.
+const MAX_NESTING: usize = 64;
+
+/// The MathML for `tex`, as a block when `display` is set.
+pub fn to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        pos: 0,
+        display,
+        depth: 0,
+        too_deep: false,
+    };
+    let mut body = String::new();
+    loop {
+        body.push_str(&parser.row());
+        if parser.peek().is_none() {
+            break;
+        }
+        body.push_str(&parser.stray());
+    }
+    if parser.too_deep {
+        body = error(tex.trim());
+    }
+
+    format!(
+        "<math display=\"{}\"><semantics><mrow>{}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { "block" } else { "inline" },
+        body,
+        escape_html(tex.trim())
+    )
+}
+
+/// A rendered token or group, and whether its scripts go above and below it in display
+/// math, as with `\sum`.
+struct Atom {
+    html: String,
+    limits: bool,
+}
+
+impl Atom {
+    fn plain(html: String) -> Self {
+        Atom {
+            html,
+            limits: false,
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    /// Atoms being parsed around the cursor, each of which may hold nested rows.
+    depth: usize,
+    /// Set when [`MAX_NESTING`] was reached; the rest of the input is skipped.
+    too_deep: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// The command at the cursor without its backslash, and where it ends. Commands are a
+    /// run of letters or a single other character, as in `\frac` or `\,`.
+    fn command_at(&self) -> Option<(String, usize)> {
+        if self.peek() != Some('\\') {
+            return None;
+        }
+        let start = self.pos + 1;
+        let mut end = start;
+        while self.chars.get(end).is_some_and(char::is_ascii_alphabetic) {
+            end += 1;
+        }
+        if end == start && start < self.chars.len() {
+            end += 1;
+        }
+        Some((self.chars[start..end].iter().collect(), end))
+    }
+
+    /// Whether the cursor is at something that ends a group, cell or row.
+    fn at_row_end(&self) -> bool {
+        match self.peek() {
+            None | Some('}') | Some('&') => true,
+            Some('\\') => self
+                .command_at()
+                .is_some_and(|(name, _)| matches!(name.as_str(), "\\" | "end" | "right")),
+            _ => false,
+        }
+    }
+
+    /// Atoms and their scripts up to the end of the current group, cell or row.
+    fn row(&mut self) -> String {
+        let mut html = String::new();
+        loop {
+            self.skip_whitespace();
+            if self.at_row_end() {
+                return html;
+            }
+            let atom = self.atom();
+            html.push_str(&self.scripts(atom));
+        }
+    }
+
+    /// Renders whatever ended a row where nothing was expecting it, and moves past it.
+    fn stray(&mut self) -> String {
+        match self.command_at() {
+            Some((name, end)) => {
+                self.pos = end;
+                match name.as_str() {
+                    "right" => self.delimiter(),
+                    "end" => {
+                        let environment = self.text_argument();
+                        error(&format!("\\end{{{}}}", environment))
+                    }
+                    _ => String::new(),
+                }
+            }
+            None => match self.next() {
+                Some('&') | None => String::new(),
+                Some(c) => format!("<mo>{}</mo>", escape_char(c)),
+            },
+        }
+    }
+
+    /// Attaches any `_` and `^` following `base`.
+    fn scripts(&mut self, base: Atom) -> String {
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.argument());
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.argument());
+                }
+                _ => break,
+            }
+        }
+
+        let limits = base.limits && self.display;
+        let (tag, scripts) = match (sub, sup) {
+            (None, None) => return base.html,
+            (Some(sub), None) => (if limits { "munder" } else { "msub" }, sub),
+            (None, Some(sup)) => (if limits { "mover" } else { "msup" }, sup),
+            (Some(sub), Some(sup)) => (if limits { "munderover" } else { "msubsup" }, sub + &sup),
+        };
+        format!("<{0}>{1}{2}</{0}>", tag, base.html, scripts)
+    }
+
+    /// A script or command argument: a braced group or a single token.
+    fn argument(&mut self) -> String {
+        self.skip_whitespace();
+        if self.at_row_end() {
+            return "<mrow></mrow>".into();
+        }
+        self.atom().html
+    }
+
+    /// A `{...}` group, with the cursor on its opening brace.
+    fn group(&mut self) -> String {
+        self.pos += 1;
+        let mut html = String::new();
+        loop {
+            html.push_str(&self.row());
+            match self.peek() {
+                None => break,
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => html.push_str(&self.stray()),
+            }
+        }
+        format!("<mrow>{}</mrow>", html)
+    }
+
+    /// The literal text of a braced argument, or of the next character without braces.
+    fn text_argument(&mut self) -> String {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return self.next().map(String::from).unwrap_or_default();
+        }
+        self.pos += 1;
+        let mut depth = 0;
+        let mut text = String::new();
+        while let Some(c) = self.next() {
+            match c {
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                '{' => depth += 1,
+                _ => {}
+            }
+            text.push(c);
+        }
+        text
+    }
+
+    fn atom(&mut self) -> Atom {
+        if self.depth == MAX_NESTING {
+            self.too_deep = true;
+            self.pos = self.chars.len();
+            return Atom::plain(String::new());
+        }
+        self.depth += 1;
+        let atom = self.nested_atom();
+        self.depth -= 1;
+        atom
+    }
+
+    fn nested_atom(&mut self) -> Atom {
+        let Some(c) = self.peek() else {
+            return Atom::plain(String::new());
+        };
+        match c {
+            '\\' => return self.command(),
+            '{' => return Atom::plain(self.group()),
+            _ => self.pos += 1,
+        }
+
+        Atom::plain(match c {
+            '0'..='9' => {
+                let mut number = c.to_string();
+                while let Some(next) = self.peek().filter(|next| {
+                    next.is_ascii_digit()
+                        || (*next == '.'
+                            && self
+                                .chars
+                                .get(self.pos + 1)
+                                .is_some_and(char::is_ascii_digit))
+                }) {
+                    number.push(next);
+                    self.pos += 1;
+                }
+                format!("<mn>{}</mn>", number)
+            }
+            c if c.is_alphabetic() => format!("<mi>{}</mi>", escape_char(c)),
+            '-' => "<mo>\u{2212}</mo>".into(),
+            '*' => "<mo>\u{2217}</mo>".into(),
+            '\'' => "<mo>\u{2032}</mo>".into(),
+            '~' => space("0.333em"),
+            '_' | '^' => error(&c.to_string()),
+            c => format!("<mo>{}</mo>", escape_char(c)),
+        })
+    }
+
+    /// The command at the cursor, with its arguments.
+    fn command(&mut self) -> Atom {
+        let Some((name, end)) = self.command_at() else {
+            return Atom::plain(String::new());
+        };
+        self.pos = end;
+        let name = name.as_str();
+
+        if let Some(atom) = symbol(name) {
+            return atom;
+        }
+        Atom::plain(match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let top = self.argument();
+                let bottom = self.argument();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom
+                )
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                let index = if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let start = self.pos;
+                    while self.peek().is_some_and(|c| c != ']') {
+                        self.pos += 1;
+                    }
+                    let index: String = self.chars[start..self.pos].iter().collect();
+                    self.pos += 1;
+                    Some(index)
+                } else {
+                    None
+                };
+                let radicand = self.argument();
+                match index {
+                    Some(index) => {
+                        // The index counts towards the nesting of the formula around it.
+                        let mut index_parser = Parser {
+                            chars: index.chars().collect(),
+                            pos: 0,
+                            display: false,
+                            depth: self.depth,
+                            too_deep: false,
+                        };
+                        let index = index_parser.group_of_row();
+                        self.too_deep |= index_parser.too_deep;
+                        format!("<mroot>{}{}</mroot>", radicand, index)
+                    }
+                    None => format!("<msqrt>{}</msqrt>", radicand),
+                }
+            }
+            "text" | "textrm" | "textit" | "textbf" | "textsf" | "texttt" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape_html(&self.text_argument()))
+            }
+            "operatorname" => format!("<mi>{}</mi>", escape_html(self.text_argument().trim())),
+            "mathrm" | "mathit" | "mathbf" | "boldsymbol" | "mathbb" | "mathcal" | "mathscr"
+            | "mathfrak" | "mathsf" | "mathtt" => {
+                let variant = match name {
+                    "mathrm" => "normal",
+                    "mathit" => "italic",
+                    "mathbf" | "boldsymbol" => "bold",
+                    "mathbb" => "double-struck",
+                    "mathcal" | "mathscr" => "script",
+                    "mathfrak" => "fraktur",
+                    "mathsf" => "sans-serif",
+                    _ => "monospace",
+                };
+                format!(
+                    "<mi mathvariant=\"{}\">{}</mi>",
+                    variant,
+                    escape_html(self.text_argument().trim())
+                )
+            }
+            "hat" | "widehat" | "bar" | "overline" | "vec" | "tilde" | "widetilde" | "dot"
+            | "ddot" => {
+                let accent = match name {
+                    "hat" | "widehat" => "^",
+                    "bar" | "overline" => "\u{203e}",
+                    "vec" => "\u{2192}",
+                    "tilde" | "widetilde" => "~",
+                    "dot" => "\u{02d9}",
+                    _ => "\u{00a8}",
+                };
+                format!(
+                    "<mover accent=\"true\">{}<mo>{}</mo></mover>",
+                    self.argument(),
+                    accent
+                )
+            }
+            "underline" => format!("<munder>{}<mo>_</mo></munder>", self.argument()),
+            "left" => {
+                let open = self.delimiter();
+                let body = self.row();
+                let close = match self.command_at() {
+                    Some((name, end)) if name == "right" => {
+                        self.pos = end;
+                        self.delimiter()
+                    }
+                    _ => String::new(),
+                };
+                format!("<mrow>{}{}{}</mrow>", open, body, close)
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl"
+            | "biggr" | "Biggl" | "Biggr" => self.delimiter(),
+            "begin" => self.environment(),
+            "," | ":" | ">" | ";" | " " | "quad" | "qquad" => space(match name {
+                "," => "0.167em",
+                ":" | ">" => "0.222em",
+                ";" => "0.278em",
+                " " => "0.333em",
+                "quad" => "1em",
+                _ => "2em",
+            }),
+            "!" => String::new(),
+            "{" | "}" | "%" | "$" | "#" | "&" | "_" => format!("<mo>{}</mo>", escape_html(name)),
+            "|" => "<mo>\u{2016}</mo>".into(),
+            _ => error(&format!("\\{}", name)),
+        })
+    }
+
+    /// A row parsed as a group of its own, for arguments read as text first.
+    fn group_of_row(&mut self) -> String {
+        format!("<mrow>{}</mrow>", self.row())
+    }
+
+    /// The delimiter after `\left`, `\right` or `\big`. `.` is no delimiter at all.
+    fn delimiter(&mut self) -> String {
+        self.skip_whitespace();
+        let symbol = match self.command_at() {
+            Some((name, end)) => {
+                self.pos = end;
+                match name.as_str() {
+                    "{" | "lbrace" => "{",
+                    "}" | "rbrace" => "}",
+                    "|" | "Vert" => "\u{2016}",
+                    "vert" => "|",
+                    "langle" => "\u{27e8}",
+                    "rangle" => "\u{27e9}",
+                    "lfloor" => "\u{230a}",
+                    "rfloor" => "\u{230b}",
+                    "lceil" => "\u{2308}",
+                    "rceil" => "\u{2309}",
+                    "backslash" => "\\",
+                    _ => return error(&format!("\\{}", name)),
+                }
+                .to_string()
+            }
+            None => match self.next() {
+                Some('.') | None => return String::new(),
+                Some(c) => c.to_string(),
+            },
+        };
+        format!("<mo stretchy=\"true\">{}</mo>", escape_html(&symbol))
+    }
+
+    /// `\begin{name} ... \end{name}`, with the cursor after `\begin`. Cells are separated
+    /// by `&` and rows by `\\`.
+    fn environment(&mut self) -> String {
+        let name = self.text_argument();
+        let (open, close, align) = match name.trim() {
+            "matrix" | "smallmatrix" => ("", "", "center"),
+            "pmatrix" => ("(", ")", "center"),
+            "bmatrix" => ("[", "]", "center"),
+            "Bmatrix" => ("{", "}", "center"),
+            "vmatrix" => ("|", "|", "center"),
+            "Vmatrix" => ("\u{2016}", "\u{2016}", "center"),
+            "cases" => ("{", "", "left left"),
+            "aligned" | "align" | "align*" | "split" | "gathered" => ("", "", "right left"),
+            _ => return error(&format!("\\begin{{{}}}", name)),
+        };
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut cells = Vec::new();
+        let mut cell = String::new();
+        loop {
+            cell.push_str(&self.row());
+            match self.command_at() {
+                Some((name, end)) if name == "\\" || name == "end" => {
+                    self.pos = end;
+                    cells.push(std::mem::take(&mut cell));
+                    rows.push(std::mem::take(&mut cells));
+                    if name == "end" {
+                        self.text_argument();
+                        break;
+                    }
+                }
+                Some(_) => cell.push_str(&self.stray()),
+                None => match self.peek() {
+                    Some('&') => {
+                        self.pos += 1;
+                        cells.push(std::mem::take(&mut cell));
+                    }
+                    Some(_) => cell.push_str(&self.stray()),
+                    None => {
+                        cells.push(cell);
+                        rows.push(cells);
+                        break;
+                    }
+                },
+            }
+        }
+        // A trailing `\\` before `\end` does not start another row.
+        if rows.len() > 1
+            && rows
+                .last()
+                .is_some_and(|row| row.len() == 1 && row[0].is_empty())
+        {
+            rows.pop();
+        }
+
+        let table: String = rows
+            .iter()
+            .map(|row| {
+                let cells: String = row
+                    .iter()
+                    .map(|cell| format!("<mtd>{}</mtd>", cell))
+                    .collect();
+                format!("<mtr>{}</mtr>", cells)
+            })
+            .collect();
+        let fence = |symbol: &str| {
+            if symbol.is_empty() {
+                String::new()
+            } else {
+                format!("<mo stretchy=\"true\">{}</mo>", escape_html(symbol))
+            }
+        };
+        format!(
+            "<mrow>{}<mtable columnalign=\"{}\">{}</mtable>{}</mrow>",
+            fence(open),
+            align,
+            table,
+            fence(close)
+        )
+    }
+}
+
+fn escape_char(c: char) -> String {
+    escape_html(&c.to_string())
+}
+
+fn space(width: &str) -> String {
+    format!("<mspace width=\"{}\"></mspace>", width)
+}
+
+fn error(tex: &str) -> String {
+    format!("<merror><mtext>{}</mtext></merror>", escape_html(tex))
+}
+
+/// Commands that stand for a single symbol, without arguments.
+fn symbol(name: &str) -> Option<Atom> {
+    if let Some((_, letter)) = GREEK.iter().find(|(command, _)| *command == name) {
+        let html = if letter.is_uppercase() {
+            format!("<mi mathvariant=\"normal\">{}</mi>", letter)
+        } else {
+            format!("<mi>{}</mi>", letter)
+        };
+        return Some(Atom::plain(html));
+    }
+    if let Some((_, identifier)) = IDENTIFIERS.iter().find(|(command, _)| *command == name) {
+        return Some(Atom::plain(format!("<mi>{}</mi>", identifier)));
+    }
+    if let Some((_, operator)) = OPERATORS.iter().find(|(command, _)| *command == name) {
+        return Some(Atom::plain(format!("<mo>{}</mo>", operator)));
+    }
+    if let Some((_, operator, limits)) = LARGE_OPERATORS
+        .iter()
+        .find(|(command, _, _)| *command == name)
+    {
+        return Some(Atom {
+            html: format!("<mo>{}</mo>", operator),
+            limits: *limits,
+        });
+    }
+    if FUNCTIONS.contains(&name) {
+        return Some(Atom::plain(format!("<mi>{}</mi>", name)));
+    }
+    if LIMIT_FUNCTIONS.contains(&name) {
+        return Some(Atom {
+            html: format!("<mi>{}</mi>", name),
+            limits: true,
+        });
+    }
+    None
+}
+
+const GREEK: &[(&str, char)] = &[
+    ("alpha", 'α'),
+    ("beta", 'β'),
+    ("gamma", 'γ'),
+    ("delta", 'δ'),
+    ("epsilon", 'ϵ'),
+    ("varepsilon", 'ε'),
+    ("zeta", 'ζ'),
+    ("eta", 'η'),
+    ("theta", 'θ'),
+    ("vartheta", 'ϑ'),
+    ("iota", 'ι'),
+    ("kappa", 'κ'),
+    ("lambda", 'λ'),
+    ("mu", 'μ'),
+    ("nu", 'ν'),
+    ("xi", 'ξ'),
+    ("pi", 'π'),
+    ("varpi", 'ϖ'),
+    ("rho", 'ρ'),
+    ("varrho", 'ϱ'),
+    ("sigma", 'σ'),
+    ("varsigma", 'ς'),
+    ("tau", 'τ'),
+    ("upsilon", 'υ'),
+    ("phi", 'ϕ'),
+    ("varphi", 'φ'),
+    ("chi", 'χ'),
+    ("psi", 'ψ'),
+    ("omega", 'ω'),
+    ("Gamma", 'Γ'),
+    ("Delta", 'Δ'),
+    ("Theta", 'Θ'),
+    ("Lambda", 'Λ'),
+    ("Xi", 'Ξ'),
+    ("Pi", 'Π'),
+    ("Sigma", 'Σ'),
+    ("Upsilon", 'Υ'),
+    ("Phi", 'Φ'),
+    ("Psi", 'Ψ'),
+    ("Omega", 'Ω'),
+];
+
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("ell", "ℓ"),
+    ("hbar", "ℏ"),
+    ("Re", "ℜ"),
+    ("Im", "ℑ"),
+    ("aleph", "ℵ"),
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("cdot", "⋅"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("ast", "∗"),
+    ("circ", "∘"),
+    ("le", "≤"),
+    ("leq", "≤"),
+    ("ge", "≥"),
+    ("geq", "≥"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("ne", "≠"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("propto", "∝"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("gets", "←"),
+    ("leftrightarrow", "↔"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"),
+    ("iff", "⟺"),
+    ("mapsto", "↦"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("supset", "⊃"),
+    ("supseteq", "⊇"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("neg", "¬"),
+    ("lnot", "¬"),
+    ("land", "∧"),
+    ("wedge", "∧"),
+    ("lor", "∨"),
+    ("vee", "∨"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("mid", "∣"),
+    ("parallel", "∥"),
+    ("perp", "⊥"),
+    ("angle", "∠"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("vert", "|"),
+    ("Vert", "‖"),
+    ("cdots", "⋯"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+];
+
+/// Large operators, and whether they take their limits above and below in display math.
+const LARGE_OPERATORS: &[(&str, &str, bool)] = &[
+    ("sum", "∑", true),
+    ("prod", "∏", true),
+    ("coprod", "∐", true),
+    ("bigcup", "⋃", true),
+    ("bigcap", "⋂", true),
+    ("bigoplus", "⨁", true),
+    ("bigotimes", "⨂", true),
+    ("int", "∫", false),
+    ("iint", "∬", false),
+    ("iiint", "∭", false),
+    ("oint", "∮", false),
+];
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "sec", "csc", "cot", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "deg", "dim", "ker", "arg", "hom",
+];
+
+/// Functions that, like `\lim`, take their subscript underneath in display math.
+const LIMIT_FUNCTIONS: &[&str] = &[
+    "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd", "Pr",
+];
@@ -1,19 +1,329 @@
 use axum::body::Body;
-use axum::http::request::Request;
+use axum::http::{HeaderMap, Method, StatusCode, request::Request};
 use serde_json;
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
 use tower_service::Service;
 
+use crate::config::MarkdownExtensions;
+use crate::db::{Database, ROOT_ROLE, Requirement};
+use crate::user::{JwtClaims, SESSION_COOKIE};
+use front_matter::FrontMatter;
+
+pub mod api;
+pub mod backlinks;
+pub mod csrf;
+pub mod diff;
+pub mod edit;
+pub mod front_matter;
+pub mod gates;
+pub mod highlight;
+pub mod history;
+pub mod include;
+pub mod links;
+pub mod markdown;
+pub mod math;
+pub mod nav;
+pub mod paths;
+pub mod policy;
+pub mod sanitize;
+pub mod search;
+pub mod tags;
+pub mod templates;
+pub mod toc;
+
+pub use markdown::{RenderContext, parse_markdown, render_markdown};
+
+/// The docs directory served by the app, relative to the working directory.
+pub const DOCS_ROOT: &str = "docs";
+
+/// Pages with at least this many visible headings get a table of contents at the top.
+pub const AUTO_TOC_HEADINGS: usize = 4;
+
+/// Privilege level required to edit pages unless overridden with
+/// [`ServeDocs::with_write_level`]. Guests and freshly registered users sit at level 1.
+pub const DEFAULT_WRITE_LEVEL: i32 = 2;
+
 #[derive(Clone)]
 pub struct ServeDocs {
     path: String,
+    write_level: i32,
+    database: Option<Database>,
+    markdown: MarkdownExtensions,
+    templates: Option<String>,
 }
 
 impl ServeDocs {
     pub fn new(path: &str) -> Self {
-        ServeDocs { path: path.into() }
+        ServeDocs {
+            path: path.into(),
+            write_level: DEFAULT_WRITE_LEVEL,
+            database: None,
+            markdown: MarkdownExtensions::default(),
+            templates: None,
+        }
+    }
+
+    pub fn with_write_level(mut self, write_level: i32) -> Self {
+        self.write_level = write_level;
+        self
+    }
+
+    /// Renders pages with `extensions` instead of the GitHub-style defaults.
+    pub fn with_markdown_extensions(mut self, extensions: MarkdownExtensions) -> Self {
+        self.markdown = extensions;
+        self
+    }
+
+    /// Offers the templates under `path` when a page is created (see [`templates`]).
+    pub fn with_templates(mut self, path: &str) -> Self {
+        self.templates = Some(path.into());
+        self
+    }
+
+    /// Uses `database` for revisions instead of the global [`crate::DB`].
+    pub fn with_database(mut self, database: Database) -> Self {
+        self.database = Some(database);
+        self
+    }
+}
+
+/// Who is making a request, as far as the `Authorization` header tells us.
+pub struct Caller {
+    pub username: String,
+    pub permissions: i32,
+    pub roles: Vec<String>,
+    /// The login session the caller's token was issued for, if any.
+    pub session: Option<i64>,
+    /// Identified by the session cookie rather than an `Authorization` header. Browsers
+    /// attach cookies to cross-site requests too, so such callers need a [`csrf`] token.
+    pub via_cookie: bool,
+}
+
+impl Caller {
+    pub(crate) fn guest() -> Self {
+        Caller {
+            username: "guest".into(),
+            permissions: 1,
+            roles: Vec::new(),
+            session: None,
+            via_cookie: false,
+        }
+    }
+
+    /// From the `Authorization` header, or else the session cookie. `None` when there are
+    /// no credentials at all, or only an expired cookie that the bootstrap page can
+    /// refresh; an invalid bearer token is treated as a guest.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let Some(authorization) = headers.get("Authorization") else {
+            let jwt = crate::user::cookie(headers, SESSION_COOKIE)?;
+            return crate::user::decode_jwt(jwt).map(|claims| Caller {
+                via_cookie: true,
+                ..Caller::from_claims(claims)
+            });
+        };
+        let jwt = authorization
+            .to_str()
+            .unwrap_or("")
+            .strip_prefix("Bearer ")
+            .unwrap_or("");
+        Some(
+            crate::user::decode_jwt(jwt)
+                .map(Caller::from_claims)
+                .unwrap_or_else(Caller::guest),
+        )
+    }
+
+    fn from_claims(claims: JwtClaims) -> Self {
+        Caller {
+            username: claims.sub,
+            // Holding the root role is as good as privilege level 0.
+            permissions: if claims.roles.iter().any(|role| role == ROOT_ROLE) {
+                0
+            } else {
+                claims.privileges
+            },
+            roles: claims.roles,
+            session: claims.sid,
+            via_cookie: false,
+        }
+    }
+
+    pub fn satisfies(&self, requirement: &Requirement) -> bool {
+        requirement.allows(self.permissions, &self.roles)
+    }
+}
+
+/// Root (privilege 0) may do anything; everyone else needs at least `required_level`.
+pub fn has_privilege(permissions: i32, required_level: i32) -> bool {
+    permissions == 0 || permissions >= required_level
+}
+
+fn query_flag(query: Option<&str>, name: &str) -> bool {
+    query
+        .map(|q| {
+            q.split('&')
+                .any(|pair| pair.split('=').next() == Some(name))
+        })
+        .unwrap_or(false)
+}
+
+fn query_value(query: Option<&str>, name: &str) -> Option<String> {
+    query?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        // Forms submitted with GET encode spaces as `+`.
+        (key == name).then(|| {
+            urlencoding::decode(&value.replace('+', " "))
+                .map(|value| value.into_owned())
+                .unwrap_or_default()
+        })
+    })
+}
+
+/// Lists every Markdown page under `root` by page key, sorted, skipping hidden files such
+/// as the temporaries left behind by interrupted saves.
+pub async fn page_keys(root: &str) -> std::io::Result<Vec<String>> {
+    fn collect(dir: &Path, prefix: &str, pages: &mut Vec<String>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+
+            // Symlinks are skipped rather than followed, so nothing outside the root is
+            // ever indexed.
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                collect(&entry.path(), &format!("{}{}/", prefix, name), pages)?;
+            } else if let Some(stem) = name.strip_suffix(".md")
+                && file_type.is_file()
+            {
+                pages.push(format!("{}{}", prefix, stem));
+            }
+        }
+        Ok(())
+    }
+
+    let root = root.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut pages = Vec::new();
+        collect(Path::new(&root), "", &mut pages)?;
+        pages.sort();
+        Ok(pages)
+    })
+    .await
+    .expect("page walker panicked")
+}
+
+/// Reads every Markdown page under `root` as `(page, markdown)` pairs.
+pub async fn read_pages(root: &str) -> std::io::Result<Vec<(String, String)>> {
+    let mut pages = Vec::new();
+    for page in page_keys(root).await? {
+        let doc = tokio::fs::read_to_string(Path::new(root).join(format!("{}.md", page))).await?;
+        pages.push((page, doc));
+    }
+    Ok(pages)
+}
+
+/// Rebuilds the search index, link graph and tags from the pages under `root`, reading links
+/// with `extensions`. Returns the number of pages indexed.
+pub async fn reindex(
+    database: &Database,
+    root: &str,
+    extensions: &MarkdownExtensions,
+) -> std::io::Result<usize> {
+    let pages = read_pages(root).await?;
+    search::replace_index(database, &pages)
+        .await
+        .map_err(std::io::Error::other)?;
+    backlinks::replace_all(database, root, &pages, extensions)
+        .await
+        .map_err(std::io::Error::other)?;
+    tags::replace_all(database, &pages)
+        .await
+        .map_err(std::io::Error::other)?;
+    Ok(pages.len())
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
     }
+    escaped
+}
+
+/// Wraps `body` in the standard docs template.
+fn render_page(status: StatusCode, body: &str) -> axum::response::Response {
+    render_document(status, "Docs", &FrontMatter::default(), "", body)
+}
+
+/// Like [`render_page`], with the `<title>` and `<meta>` tags of a page and the `nav`
+/// sidebar beside it.
+fn render_document(
+    status: StatusCode,
+    title: &str,
+    front_matter: &FrontMatter,
+    nav: &str,
+    body: &str,
+) -> axum::response::Response {
+    let css = format!(
+        "{}\n{}",
+        include_str!("styles.css"),
+        highlight::stylesheet()
+    );
+    let js = include_str!("main.js");
+
+    let mut meta = String::new();
+    if let Some(description) = &front_matter.description {
+        meta.push_str(&format!(
+            "<meta name=\"description\" content=\"{}\">",
+            escape_html(description)
+        ));
+    }
+    if let Some(author) = &front_matter.author {
+        meta.push_str(&format!(
+            "<meta name=\"author\" content=\"{}\">",
+            escape_html(author)
+        ));
+    }
+    if !front_matter.tags.is_empty() {
+        meta.push_str(&format!(
+            "<meta name=\"keywords\" content=\"{}\">",
+            escape_html(&front_matter.tags.join(", "))
+        ));
+    }
+
+    let html = format!(
+        include_str!("format.html"),
+        title = escape_html(title),
+        meta = meta,
+        css = css,
+        js = js,
+        nav = nav,
+        body = body
+    );
+
+    axum::response::Response::builder()
+        .status(status)
+        .body(Body::from(html))
+        .unwrap()
+}
+
+fn status_response(status: StatusCode, message: &'static str) -> axum::response::Response {
+    axum::response::Response::builder()
+        .status(status)
+        .body(Body::from(message))
+        .unwrap()
 }
 
 impl Service<Request<Body>> for ServeDocs {
@@ -30,15 +340,19 @@ impl Service<Request<Body>> for ServeDocs {
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         let path = self.path.clone();
+        let write_level = self.write_level;
+        let database = self.database.clone();
+        let markdown = self.markdown.clone();
+        let templates_root = self.templates.clone();
         Box::pin(async move {
-            let permissions;
-            if let Some(jwt) = req.headers().get("Authorization") {
-                let jwt = jwt
-                    .to_str()
-                    .unwrap_or("")
-                    .strip_prefix("Bearer ")
-                    .unwrap_or("");
-                permissions = crate::user::get_jwt_perms(jwt).unwrap_or(1);
+            let caller;
+            if let Some(from_headers) = Caller::from_headers(req.headers()) {
+                caller = from_headers;
+            } else if req.method() != Method::GET {
+                return Ok(status_response(
+                    StatusCode::UNAUTHORIZED,
+                    "Authorization required",
+                ));
             } else {
                 let redirect_target = req
                     .uri()
@@ -59,91 +373,231 @@ impl Service<Request<Body>> for ServeDocs {
                     .body(Body::from(html))
                     .unwrap());
             }
+            let req = match csrf::check(req, &caller).await {
+                Ok(req) => req,
+                Err(rejection) => return Ok(rejection),
+            };
+            let permissions = caller.permissions;
+            let database = database.unwrap_or_else(|| crate::DB.clone());
 
             let uri = req.uri();
-            if uri.query().map(|q| q.contains("edit")).unwrap_or(false) {
-                let uri = uri.path();
-                let doc_path = format!("{}{}.md", path, uri);
-                let contents = tokio::fs::read_to_string(&doc_path)
-                    .await
-                    .unwrap_or_default();
+            let query = uri.query();
+            let uri_path = uri.path().to_string();
+            let Some(page) = paths::page_key(&uri_path) else {
+                return Ok(status_response(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid document path",
+                ));
+            };
+            let page = page.as_str();
+
+            // Access policies are checked before the page is touched, so a reader who may
+            // not see a page cannot even learn whether it exists.
+            let policy = match policy::for_page(&path, page).await {
+                Ok(policy) => policy,
+                Err(err) => {
+                    eprintln!("Invalid access policy: {}", err);
+                    return Ok(status_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Invalid access policy",
+                    ));
+                }
+            };
+            if !policy.can_read(&caller) {
+                return Ok(status_response(
+                    StatusCode::FORBIDDEN,
+                    "This page requires higher privileges",
+                ));
+            }
+
+            // Everything below shows or changes raw Markdown, gated sections included.
+            let revert = query_value(query, "revert");
+            let diff = query_value(query, "diff");
+            let source_view = query_flag(query, "edit") || query_flag(query, "history");
+            if (source_view || revert.is_some() || diff.is_some())
+                && !(has_privilege(permissions, write_level) && policy.can_write(&caller))
+            {
+                return Ok(status_response(
+                    StatusCode::FORBIDDEN,
+                    "Editing requires higher privileges",
+                ));
+            }
+
+            if let Some(id) = revert {
+                return Ok(if req.method() == Method::POST {
+                    history::revert(&database, &path, &caller, page, &uri_path, &id, &markdown)
+                        .await
+                } else {
+                    history::revert_form(&database, &path, &caller, page, &uri_path, &id).await
+                });
+            }
+
+            if query_flag(query, "edit") {
+                if req.method() == Method::POST {
+                    return Ok(edit::save(&database, &path, &caller, page, &markdown, req).await);
+                }
+
+                let Some(doc_path) = paths::page_file(&path, page).await else {
+                    return Ok(status_response(
+                        StatusCode::BAD_REQUEST,
+                        "Invalid document path",
+                    ));
+                };
+                let form = match tokio::fs::read_to_string(&doc_path).await {
+                    Ok(contents) => edit::render_form(&uri_path, &contents, &caller),
+                    Err(_) => {
+                        let names = match &templates_root {
+                            Some(root) => templates::list(root).await,
+                            None => Vec::new(),
+                        };
+                        let selected =
+                            query_value(query, "template").filter(|name| !name.is_empty());
+                        let contents = match (&templates_root, &selected) {
+                            (Some(root), Some(name)) if names.contains(name) => {
+                                templates::load(root, name).await.unwrap_or_default()
+                            }
+                            (_, Some(_)) => {
+                                return Ok(status_response(
+                                    StatusCode::NOT_FOUND,
+                                    "No such template",
+                                ));
+                            }
+                            (_, None) => String::new(),
+                        };
+                        edit::render_new_page_form(
+                            &uri_path,
+                            &contents,
+                            &caller,
+                            &names,
+                            selected.as_deref(),
+                        )
+                    }
+                };
 
                 let response = axum::response::Response::builder()
                     .status(200)
-                    .body(Body::from(format!(
-                        "<html><body><form method=\"post\" action=\"/docs{}?edit\"><textarea name=\"content\" rows=\"20\" cols=\"80\">{}</textarea><br><button type=\"submit\">Save</button></form></body></html>",
-                        uri, contents
-                    )))
+                    .body(Body::from(form))
                     .unwrap();
 
                 return Ok(response);
             }
-            let uri = req.uri().path();
-            let doc_path = format!("{}{}.md", path, uri);
-
-            let doc = match tokio::fs::read_to_string(&doc_path).await {
-                Ok(doc) => doc,
-                Err(_) => {
-                    return Ok(axum::response::Response::builder()
-                        .status(axum::http::StatusCode::NOT_FOUND)
-                        .body(Body::from("Not found"))
-                        .unwrap());
-                }
-            };
 
-            let html = parse_markdown(&doc, permissions);
-
-            let css = include_str!("styles.css");
-            let js = include_str!("main.js");
+            if req.method() != Method::GET {
+                return Ok(status_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "Method not allowed",
+                ));
+            }
 
-            let html = format!(include_str!("format.html"), css, js, html);
+            if let Some(search) = query_value(query, "search")
+                && page.is_empty()
+            {
+                return Ok(search::results_page(&database, &path, &search, &caller).await);
+            }
 
-            Ok(axum::response::Response::builder()
-                .status(200)
-                .body(Body::from(html))
-                .unwrap())
-        })
-    }
-}
+            if let Some(tag) = query_value(query, "tag")
+                && page.is_empty()
+            {
+                return Ok(tags::tag_page(&database, &path, &tag, &caller).await);
+            }
 
-pub fn parse_markdown(doc: &str, permissions: i32) -> String {
-    let mut sections = Vec::new();
-    let mut current_section = String::new();
-    let mut skip_section = false;
+            if query_flag(query, "history") {
+                return Ok(history::history(&database, page, &uri_path).await);
+            }
 
-    for line in doc.lines() {
-        if line.starts_with('!') {
-            if !current_section.is_empty() && !skip_section {
-                sections.push(std::mem::take(&mut current_section));
-            } else {
-                current_section.clear();
+            if let Some(spec) = diff {
+                return Ok(history::diff(&database, page, &uri_path, &spec).await);
             }
 
-            let required_level = line[1..]
-                .chars()
-                .next()
-                .and_then(|c| c.to_digit(10))
-                .unwrap_or(1) as i32;
+            let mut page = page.to_string();
+            let doc = match paths::page_file(&path, &page).await {
+                Some(file) => tokio::fs::read_to_string(file).await.ok(),
+                None => None,
+            };
+            let doc = match doc {
+                Some(doc) => doc,
+                None => {
+                    if paths::directory(&path, &page).await.is_none() {
+                        return Ok(axum::response::Response::builder()
+                            .status(axum::http::StatusCode::NOT_FOUND)
+                            .body(Body::from("Not found"))
+                            .unwrap());
+                    }
 
-            skip_section = permissions != 0 && permissions < required_level;
-        } else {
-            current_section.push_str(line);
-            current_section.push('\n');
-        }
-    }
-    if !current_section.is_empty() && !skip_section {
-        sections.push(current_section);
-    }
+                    let dir = page.clone();
+                    match policy::for_directory(&path, &dir).await {
+                        Ok(policy) if policy.can_read(&caller) => {}
+                        Ok(_) => {
+                            return Ok(status_response(
+                                StatusCode::FORBIDDEN,
+                                "This page requires higher privileges",
+                            ));
+                        }
+                        Err(err) => {
+                            eprintln!("Invalid access policy: {}", err);
+                            return Ok(status_response(
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Invalid access policy",
+                            ));
+                        }
+                    }
+
+                    // An `index.md` the caller may read replaces the generated index.
+                    let index = nav::index_page(&dir);
+                    let index_doc = match paths::page_file(&path, &index).await {
+                        Some(file) => tokio::fs::read_to_string(file).await.ok(),
+                        None => None,
+                    };
+                    match index_doc {
+                        Some(doc) if policy::readable(&path, &index, &caller).await => {
+                            page = index;
+                            doc
+                        }
+                        _ => {
+                            let pages = nav::readable_pages(&path, &caller).await;
+                            let sidebar = nav::sidebar(&pages, &index);
+                            return Ok(nav::directory_index(&dir, &pages, &sidebar));
+                        }
+                    }
+                }
+            };
+            let page = page.as_str();
+
+            let includes = include::load(&path, &doc, &caller).await;
+            let mut docs: Vec<&str> = vec![&doc];
+            docs.extend(includes.values().map(String::as_str));
+            let links = links::load(&path, &docs, &markdown, Some(&caller)).await;
+            let context = RenderContext {
+                links: Some(links),
+                auto_toc: Some(AUTO_TOC_HEADINGS),
+                extensions: markdown,
+                page: Some(page.to_string()),
+                includes,
+            };
+            let mut html = render_markdown(&doc, permissions, &caller.roles, &context);
 
-    let page = sections
-        .into_iter()
-        .map(|section| comrak::markdown_to_html(&section, &comrak::Options::default()))
-        .collect::<Vec<_>>()
-        .join("\n");
+            // Keeps the link graph and tags in step with files edited outside the wiki.
+            if let Err(err) =
+                backlinks::index_document(&database, &path, page, &doc, &context.extensions).await
+            {
+                eprintln!("Failed to update links of {}: {}", page, err);
+            }
+            if let Err(err) = tags::index_document(&database, page, &doc).await {
+                eprintln!("Failed to update tags of {}: {}", page, err);
+            }
+            html.push_str(&tags::list(&tags::page_tags(page, &doc)));
+            html.push_str(&backlinks::panel(&database, &path, page, &caller).await);
 
-    if page.trim().is_empty() {
-        "Page requires higher privileges, try logging in.".into()
-    } else {
-        page
+            let front_matter = front_matter::parse_or_default(page, &doc);
+            let title = front_matter.title.as_deref().unwrap_or(page);
+            let sidebar = nav::sidebar(&nav::readable_pages(&path, &caller).await, page);
+            Ok(render_document(
+                StatusCode::OK,
+                title,
+                &front_matter,
+                &sidebar,
+                &html,
+            ))
+        })
     }
 }
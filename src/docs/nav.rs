@@ -0,0 +1,176 @@
+//! Directory indexes and the sidebar listing the docs hierarchy.
+
+use axum::http::StatusCode;
+use std::collections::BTreeMap;
+
+use super::front_matter::FrontMatter;
+use super::{Caller, escape_html, links, page_keys, policy, render_document};
+
+/// The page a directory shows instead of its generated index.
+pub const INDEX_PAGE: &str = "index";
+
+/// Every page under `root` that `caller` may read, sorted by page key.
+pub async fn readable_pages(root: &str, caller: &Caller) -> Vec<String> {
+    let pages = match page_keys(root).await {
+        Ok(pages) => pages,
+        Err(err) => {
+            eprintln!("Failed to list pages under {}: {}", root, err);
+            return Vec::new();
+        }
+    };
+
+    let mut readable = Vec::with_capacity(pages.len());
+    for page in pages {
+        if policy::readable(root, &page, caller).await {
+            readable.push(page);
+        }
+    }
+    readable
+}
+
+/// The page key of the index page of `dir`.
+pub fn index_page(dir: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        INDEX_PAGE.to_string()
+    } else {
+        format!("{}/{}", dir, INDEX_PAGE)
+    }
+}
+
+pub fn directory_url(dir: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        "/docs/".to_string()
+    } else {
+        format!("/docs/{}/", links::encode_page(dir))
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    /// Whether a page with this exact key exists, as opposed to only a directory.
+    page: bool,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, page: &str) {
+        let mut node = self;
+        for segment in page.split('/') {
+            node = node.children.entry(segment.to_string()).or_default();
+        }
+        node.page = true;
+    }
+}
+
+/// The sidebar tree of `pages`, with `current` (a page key) marked. Directories link to
+/// their index, so `index` pages themselves are left out.
+pub fn sidebar(pages: &[String], current: &str) -> String {
+    let mut tree = Node::default();
+    for page in pages {
+        tree.insert(page);
+    }
+
+    let mut html = String::from("<nav class=\"docs-nav\" aria-label=\"Docs\">");
+    html.push_str(&format!(
+        "<a href=\"/docs/\"{}>Docs</a>",
+        current_marker(current == INDEX_PAGE)
+    ));
+    render_tree(&tree, "", current, &mut html);
+    html.push_str("</nav>");
+    html
+}
+
+fn render_tree(node: &Node, prefix: &str, current: &str, html: &mut String) {
+    let entries: Vec<(&String, &Node)> = node
+        .children
+        .iter()
+        .filter(|(name, child)| name.as_str() != INDEX_PAGE || !child.children.is_empty())
+        .collect();
+    if entries.is_empty() {
+        return;
+    }
+
+    html.push_str("<ul>");
+    for (name, child) in entries {
+        let key = format!("{}{}", prefix, name);
+        // A page that shares its name with a directory wins the link.
+        let (url, is_current) = if child.page {
+            (links::page_url(&key), key == current)
+        } else {
+            (directory_url(&key), index_page(&key) == current)
+        };
+        html.push_str(&format!(
+            "<li><a href=\"{}\"{}>{}</a>",
+            escape_html(&url),
+            current_marker(is_current),
+            escape_html(name)
+        ));
+        render_tree(child, &format!("{}/", key), current, html);
+        html.push_str("</li>");
+    }
+    html.push_str("</ul>");
+}
+
+fn current_marker(is_current: bool) -> &'static str {
+    if is_current {
+        " aria-current=\"page\""
+    } else {
+        ""
+    }
+}
+
+/// The generated index of `dir`: its readable pages and the subdirectories holding any.
+pub fn directory_index(dir: &str, pages: &[String], nav: &str) -> axum::response::Response {
+    let dir = dir.trim_matches('/');
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+
+    let mut subdirectories: Vec<&str> = Vec::new();
+    let mut items = String::new();
+    for page in pages {
+        let Some(rest) = page.strip_prefix(&prefix) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            Some((subdirectory, _)) if subdirectories.last() != Some(&subdirectory) => {
+                subdirectories.push(subdirectory);
+            }
+            Some(_) => {}
+            None if rest != INDEX_PAGE => items.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape_html(&links::page_url(page)),
+                escape_html(rest)
+            )),
+            None => {}
+        }
+    }
+
+    let directories: String = subdirectories
+        .iter()
+        .map(|subdirectory| {
+            format!(
+                "<li><a href=\"{}\">{}/</a></li>",
+                escape_html(&directory_url(&format!("{}{}", prefix, subdirectory))),
+                escape_html(subdirectory)
+            )
+        })
+        .collect();
+
+    let title = if dir.is_empty() { "Docs" } else { dir };
+    let mut html = format!("<h1>{}</h1>", escape_html(title));
+    if directories.is_empty() && items.is_empty() {
+        html.push_str("<p>There are no pages here.</p>");
+    } else {
+        html.push_str(&format!(
+            "<ul class=\"directory-index\">{}{}</ul>",
+            directories, items
+        ));
+    }
+
+    render_document(StatusCode::OK, title, &FrontMatter::default(), nav, &html)
+}
@@ -0,0 +1,75 @@
+//! The one place request paths become files. Every endpoint that reads or writes a page
+//! goes through [`page_key`] (or [`normalize`] for already-decoded paths) and then
+//! [`page_file`] or [`directory`], so traversal rules live here and nowhere else:
+//!
+//! - percent-escapes are decoded exactly once, and must decode to UTF-8;
+//! - empty and `.` segments are dropped, `..` is refused rather than resolved;
+//! - hidden segments (`.access.toml`, in-flight save temporaries) are refused;
+//! - backslashes and control characters are refused;
+//! - the resolved file, or its closest existing ancestor, must canonicalize to somewhere
+//!   inside the canonical docs root, so symlinks cannot lead out of it.
+
+use std::path::{Path, PathBuf};
+
+/// The page key of a request path such as `/plans/serve%20docs`, or `None` when the path
+/// is not one a page could live at. The docs root itself is the empty key.
+pub fn page_key(uri_path: &str) -> Option<String> {
+    normalize(&urlencoding::decode(uri_path).ok()?)
+}
+
+/// Like [`page_key`], for a path that has already been percent-decoded.
+pub fn normalize(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            _ if segment.starts_with('.') => return None,
+            _ if segment.chars().any(|c| c == '\\' || c.is_control()) => return None,
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// The Markdown file of `page` under `root`, whether or not it exists yet.
+pub async fn page_file(root: &str, page: &str) -> Option<PathBuf> {
+    let page = normalize(page)?;
+    if page.is_empty() {
+        return None;
+    }
+    contained(root, Path::new(root).join(format!("{}.md", page))).await
+}
+
+/// The directory `page` names under `root`, if there is one. The empty key is the root.
+pub async fn directory(root: &str, page: &str) -> Option<PathBuf> {
+    let page = normalize(page)?;
+    let path = contained(root, Path::new(root).join(page)).await?;
+    let metadata = tokio::fs::metadata(&path).await.ok()?;
+    metadata.is_dir().then_some(path)
+}
+
+/// `target` when it stays inside `root` once symlinks are followed.
+async fn contained(root: &str, target: PathBuf) -> Option<PathBuf> {
+    let root = match tokio::fs::canonicalize(root).await {
+        Ok(root) => root,
+        // Nothing can be linked out of a root that does not exist yet; the lexical
+        // checks in `normalize` are all there is.
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Some(target),
+        Err(_) => return None,
+    };
+
+    // New pages do not exist yet, so check the closest ancestor that does.
+    let mut existing = target.as_path();
+    let canonical = loop {
+        match tokio::fs::canonicalize(existing).await {
+            Ok(canonical) => break canonical,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                existing = existing.parent()?;
+            }
+            Err(_) => return None,
+        }
+    };
+
+    canonical.starts_with(&root).then_some(target)
+}
@@ -0,0 +1,162 @@
+//! Access policies for whole pages and directories.
+//!
+//! Any directory under the docs root may hold a `.access.toml`:
+//!
+//! ```toml
+//! # Everything in this directory and below it.
+//! read = 2
+//! write = "@finance"
+//!
+//! # Only `budget.md` in this directory.
+//! [pages.budget]
+//! read = "@finance"
+//! ```
+//!
+//! A rule is a privilege level or a `@role`. Policies along the path to a page all apply, so
+//! a subdirectory can tighten but never loosen its parent. Writing also needs read access
+//! and the server-wide write level.
+//!
+//! Per-page rules live only in these files, never in a page's front matter: access is
+//! decided before the page file is opened.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::Caller;
+use crate::db::{Requirement, is_valid_role};
+
+pub const POLICY_FILE: &str = ".access.toml";
+
+/// What it takes to read and to write one page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub read: Requirement,
+    pub write: Requirement,
+}
+
+impl Policy {
+    pub fn can_read(&self, caller: &Caller) -> bool {
+        caller.satisfies(&self.read)
+    }
+
+    pub fn can_write(&self, caller: &Caller) -> bool {
+        self.can_read(caller) && caller.satisfies(&self.write)
+    }
+
+    fn tighten(&mut self, read: Option<&Rule>, write: Option<&Rule>) -> Result<(), String> {
+        if let Some(rule) = read {
+            self.read = self.read.and(&rule.requirement()?);
+        }
+        if let Some(rule) = write {
+            self.write = self.write.and(&rule.requirement()?);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Rule {
+    Level(i32),
+    Role(String),
+}
+
+impl Rule {
+    fn requirement(&self) -> Result<Requirement, String> {
+        match self {
+            Rule::Level(level) => Ok(Requirement::from(*level)),
+            Rule::Role(role) => match role.strip_prefix('@') {
+                Some(name) if is_valid_role(name) => Ok(Requirement::role(name)),
+                _ => Err(format!("`{}` is not a level or `@role`", role)),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rules {
+    read: Option<Rule>,
+    write: Option<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    read: Option<Rule>,
+    write: Option<Rule>,
+    #[serde(default)]
+    pages: HashMap<String, Rules>,
+}
+
+async fn load(dir: &Path) -> Result<Option<PolicyFile>, String> {
+    let path = dir.join(POLICY_FILE);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(text) => toml::from_str(&text)
+            .map(Some)
+            .map_err(|err| format!("{}: {}", path.display(), err)),
+        // A page key whose parent is a file rather than a directory has no policy there.
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+/// The policy of `page` (a page key such as `plans/q3/budget`) under `root`. Only policy
+/// files are read, never the page itself. An unreadable or malformed policy file is an
+/// error so that a typo never opens a page up.
+pub async fn for_page(root: &str, page: &str) -> Result<Policy, String> {
+    let segments: Vec<&str> = page.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.split_last() {
+        Some((name, parents)) => collect(root, parents, Some(name)).await,
+        None => collect(root, &[], None).await,
+    }
+}
+
+/// The policy of the directory `dir` under `root`, including its own policy file.
+pub async fn for_directory(root: &str, dir: &str) -> Result<Policy, String> {
+    let segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    collect(root, &segments, None).await
+}
+
+/// Applies the policy files of `root` and each directory in `dirs` below it, then the
+/// `[pages.<name>]` rules of the last one.
+async fn collect(root: &str, dirs: &[&str], name: Option<&str>) -> Result<Policy, String> {
+    let mut policy = Policy::default();
+    let mut dir = Path::new(root).to_path_buf();
+    let mut file = load(&dir).await?;
+    for segment in dirs {
+        if let Some(file) = &file {
+            policy.tighten(file.read.as_ref(), file.write.as_ref())?;
+        }
+        dir.push(segment);
+        file = load(&dir).await?;
+    }
+
+    if let Some(file) = &file {
+        policy.tighten(file.read.as_ref(), file.write.as_ref())?;
+        if let Some(rules) = name.and_then(|name| file.pages.get(name)) {
+            policy.tighten(rules.read.as_ref(), rules.write.as_ref())?;
+        }
+    }
+
+    Ok(policy)
+}
+
+/// Whether `caller` may read `page`. Pages with a broken policy count as unreadable.
+pub async fn readable(root: &str, page: &str, caller: &Caller) -> bool {
+    match for_page(root, page).await {
+        Ok(policy) => policy.can_read(caller),
+        Err(err) => {
+            eprintln!("Invalid access policy: {}", err);
+            false
+        }
+    }
+}
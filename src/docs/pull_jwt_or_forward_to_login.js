@@ -1,51 +1,42 @@
 (function () {
   const REDIRECT_TARGET = __REDIRECT_TARGET__;
+  const COOKIE_REFRESHED_AT = "session-cookie-refreshed-at";
 
   function redirectToLogin(target) {
     const encoded = encodeURIComponent(target);
     window.location.replace(`/login/?redirect=${encoded}`);
   }
 
-  async function fetchDocument(token) {
-    return fetch(window.location.href, {
-      method: "GET",
+  // This page is only served when the session cookie is missing or expired. If the
+  // refresh cookie still holds a session, renew both and let the server render the page.
+  // Reloading at most once in a while keeps a rejected cookie from looping.
+  async function refreshSessionCookie() {
+    const last = Number(sessionStorage.getItem(COOKIE_REFRESHED_AT) || 0);
+    if (Date.now() - last < 10000) {
+      return false;
+    }
+
+    const response = await fetch("/api/refresh", {
+      method: "POST",
       credentials: "same-origin",
-      headers: {
-        "Authorization": `Bearer ${token}`,
-      },
     });
+    if (!response.ok) {
+      return false;
+    }
+    sessionStorage.setItem(COOKIE_REFRESHED_AT, String(Date.now()));
+    return true;
   }
 
   async function loadDocument() {
-    const token = localStorage.getItem("jwt");
-    if (!token) {
-      redirectToLogin(REDIRECT_TARGET);
-      return;
-    }
-
     try {
-      const response = await fetchDocument(token);
-
-      if (response.status === 401) {
-        localStorage.removeItem("jwt");
-        redirectToLogin(REDIRECT_TARGET);
+      if (await refreshSessionCookie()) {
+        window.location.reload();
         return;
       }
-
-      if (!response.ok) {
-        console.error("Failed to load document", response.status);
-        document.body.innerHTML = "<p>Unable to load document. Please try again later.</p>";
-        return;
-      }
-
-      const html = await response.text();
-      document.open();
-      document.write(html);
-      document.close();
     } catch (error) {
-      console.error("Failed to load document", error);
-      redirectToLogin(REDIRECT_TARGET);
+      console.error("Failed to refresh the session", error);
     }
+    redirectToLogin(REDIRECT_TARGET);
   }
 
   document.addEventListener("DOMContentLoaded", () => {
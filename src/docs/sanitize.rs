@@ -0,0 +1,143 @@
+//! The last stage of rendering: rendered Markdown is passed through an allowlist of tags
+//! and attributes, so even if raw HTML or a hostile URL gets past comrak, no script,
+//! event handler or `javascript:` link reaches the reader.
+
+use ammonia::{Builder, UrlRelative};
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// Everything comrak emits with the extensions in [`crate::config::MarkdownExtensions`],
+/// plus the table of contents.
+const TAGS: &[&str] = &[
+    "a",
+    "blockquote",
+    "br",
+    "code",
+    "dd",
+    "del",
+    "dl",
+    "dt",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "img",
+    "input",
+    "li",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "span",
+    "strong",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// The MathML [`super::math`] emits for `$...$` and `$$...$$`.
+const MATH_TAGS: &[&str] = &[
+    "annotation",
+    "math",
+    "merror",
+    "mfrac",
+    "mi",
+    "mn",
+    "mo",
+    "mover",
+    "mroot",
+    "mrow",
+    "mspace",
+    "msqrt",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "semantics",
+];
+
+/// Per-tag attributes. Classes are kept for highlighting, footnotes, heading permalinks
+/// and include errors; ids for heading and footnote anchors.
+const TAG_ATTRIBUTES: &[(&str, &[&str])] = &[
+    (
+        "a",
+        &[
+            "href",
+            "id",
+            "class",
+            "aria-label",
+            "data-wikilink",
+            "data-footnote-ref",
+            "data-footnote-backref",
+            "data-footnote-backref-idx",
+        ],
+    ),
+    ("img", &["src", "alt"]),
+    ("h1", &["id"]),
+    ("h2", &["id"]),
+    ("h3", &["id"]),
+    ("h4", &["id"]),
+    ("h5", &["id"]),
+    ("h6", &["id"]),
+    ("li", &["id"]),
+    ("p", &["class"]),
+    ("ol", &["start"]),
+    ("th", &["align"]),
+    ("td", &["align"]),
+    ("input", &["checked", "disabled"]),
+    ("pre", &["class"]),
+    ("code", &["class"]),
+    ("span", &["class"]),
+    ("sup", &["class"]),
+    ("nav", &["class", "aria-label"]),
+    ("section", &["class", "data-footnotes"]),
+    ("math", &["display"]),
+    ("annotation", &["encoding"]),
+    ("mi", &["mathvariant"]),
+    ("mo", &["stretchy"]),
+    ("mover", &["accent"]),
+    ("mfrac", &["linethickness"]),
+    ("mspace", &["width"]),
+    ("mtable", &["columnalign"]),
+];
+
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let tag_attributes: HashMap<&str, HashSet<&str>> = TAG_ATTRIBUTES
+        .iter()
+        .map(|(tag, attributes)| (*tag, attributes.iter().copied().collect()))
+        .collect();
+
+    let mut builder = Builder::default();
+    builder
+        .tags(TAGS.iter().chain(MATH_TAGS).copied().collect())
+        .tag_attributes(tag_attributes)
+        .generic_attributes(["title"].into_iter().collect())
+        .add_tag_attribute_values("input", "type", &["checkbox"])
+        .url_schemes(URL_SCHEMES.iter().copied().collect())
+        .url_relative(UrlRelative::PassThrough)
+        // Links are to our own pages or ones authors chose; leave `rel` to them.
+        .link_rel(None);
+    builder
+});
+
+/// Strips everything from `html` that is not on the allowlist.
+pub fn sanitize(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
@@ -0,0 +1,133 @@
+use axum::Json;
+use axum::extract::Query;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+use super::gates::gated_fragments;
+use super::{Caller, DOCS_ROOT, escape_html, links, policy, render_page};
+use crate::db::{Database, IndexedPage, MATCH_END, MATCH_START, Requirement, SearchHit};
+
+const RESULT_LIMIT: usize = 50;
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    page: String,
+    url: String,
+    snippet: String,
+}
+
+/// The `(requirement, text)` pairs stored in the search index for a page.
+pub fn index_sections(doc: &str) -> Vec<(Requirement, String)> {
+    gated_fragments(doc)
+}
+
+pub async fn index_document(database: &Database, page: &str, doc: &str) -> rusqlite::Result<()> {
+    database.index_page(page, index_sections(doc)).await
+}
+
+/// Rebuilds the search index from `(page, markdown)` pairs.
+pub async fn replace_index(
+    database: &Database,
+    pages: &[(String, String)],
+) -> rusqlite::Result<()> {
+    let pages: Vec<IndexedPage> = pages
+        .iter()
+        .map(|(page, doc)| (page.clone(), index_sections(doc)))
+        .collect();
+    database.replace_index(pages).await
+}
+
+/// Searches as `caller`, dropping hits on pages whose access policy hides them.
+async fn readable_hits(
+    database: &Database,
+    root: &str,
+    query: &str,
+    caller: &Caller,
+) -> rusqlite::Result<Vec<SearchHit>> {
+    let hits = database
+        .search(query, caller.permissions, &caller.roles, RESULT_LIMIT)
+        .await?;
+    let mut readable = Vec::with_capacity(hits.len());
+    for hit in hits {
+        if policy::readable(root, &hit.page, caller).await {
+            readable.push(hit);
+        }
+    }
+    Ok(readable)
+}
+
+/// Escapes a snippet for HTML and turns the match markers into `<mark>` tags.
+fn highlight(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+/// `GET /api/search?q=`
+pub async fn search_handler(
+    headers: HeaderMap,
+    Query(params): Query<SearchParams>,
+) -> impl IntoResponse {
+    let caller = Caller::from_headers(&headers).unwrap_or_else(Caller::guest);
+
+    match readable_hits(&crate::DB, DOCS_ROOT, &params.q, &caller).await {
+        Ok(hits) => {
+            let results: Vec<SearchResult> = hits
+                .into_iter()
+                .map(|hit| SearchResult {
+                    url: links::page_url(&hit.page),
+                    snippet: hit.snippet.replace([MATCH_START, MATCH_END], ""),
+                    page: hit.page,
+                })
+                .collect();
+            (StatusCode::OK, Json(results)).into_response()
+        }
+        Err(err) => search_error(err),
+    }
+}
+
+/// `GET /docs?search=`
+pub async fn results_page(
+    database: &Database,
+    root: &str,
+    query: &str,
+    caller: &Caller,
+) -> Response {
+    let hits: Vec<SearchHit> = match readable_hits(database, root, query, caller).await {
+        Ok(hits) => hits,
+        Err(err) => return search_error(err),
+    };
+
+    let mut html = format!(
+        "<form class=\"search\" method=\"get\" action=\"/docs\"><input name=\"search\" value=\"{}\"><button type=\"submit\">Search</button></form>",
+        escape_html(query)
+    );
+    if hits.is_empty() {
+        html.push_str("<p>No pages matched your search.</p>");
+    } else {
+        html.push_str("<ul class=\"search-results\">");
+        for hit in hits {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a><p>{}</p></li>",
+                escape_html(&links::page_url(&hit.page)),
+                escape_html(&hit.page),
+                highlight(&hit.snippet)
+            ));
+        }
+        html.push_str("</ul>");
+    }
+
+    render_page(StatusCode::OK, &html)
+}
+
+fn search_error(err: rusqlite::Error) -> Response {
+    eprintln!("Search failed: {}", err);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Search failed").into_response()
+}
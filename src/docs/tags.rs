@@ -0,0 +1,129 @@
+use axum::Json;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+use super::{Caller, DOCS_ROOT, escape_html, front_matter, links, policy, render_page};
+use crate::db::{Database, PageTags};
+
+#[derive(Serialize)]
+struct TagCount {
+    tag: String,
+    count: usize,
+    url: String,
+}
+
+/// The tags declared in the front matter of `doc`, trimmed, lowercased and deduplicated.
+pub fn page_tags(page: &str, doc: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in front_matter::parse_or_default(page, doc).tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+pub fn tag_url(tag: &str) -> String {
+    format!("/docs?tag={}", urlencoding::encode(tag))
+}
+
+/// Records the tags of `page`.
+pub async fn index_document(database: &Database, page: &str, doc: &str) -> rusqlite::Result<()> {
+    database.replace_tags(page, page_tags(page, doc)).await
+}
+
+/// Rebuilds every page's tags from `(page, markdown)` pairs.
+pub async fn replace_all(database: &Database, pages: &[(String, String)]) -> rusqlite::Result<()> {
+    let tags: Vec<PageTags> = pages
+        .iter()
+        .map(|(page, doc)| (page.clone(), page_tags(page, doc)))
+        .collect();
+    database.replace_all_tags(tags).await
+}
+
+/// The tag links shown under a page, or nothing when it has no tags.
+pub fn list(tags: &[String]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+
+    let items: String = tags
+        .iter()
+        .map(|tag| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape_html(&tag_url(tag)),
+                escape_html(tag)
+            )
+        })
+        .collect();
+    format!("<ul class=\"tags\">{}</ul>", items)
+}
+
+/// `GET /docs?tag=`
+pub async fn tag_page(database: &Database, root: &str, tag: &str, caller: &Caller) -> Response {
+    let tag = tag.trim().to_lowercase();
+    let pages = match database
+        .pages_with_tag(&tag, caller.permissions, &caller.roles)
+        .await
+    {
+        Ok(pages) => pages,
+        Err(err) => return tags_error(err),
+    };
+
+    let mut html = format!("<h1>Tagged “{}”</h1>", escape_html(&tag));
+    let mut items = String::new();
+    for page in pages {
+        if policy::readable(root, &page, caller).await {
+            items.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape_html(&links::page_url(&page)),
+                escape_html(&page)
+            ));
+        }
+    }
+    if items.is_empty() {
+        html.push_str("<p>No pages have this tag.</p>");
+    } else {
+        html.push_str(&format!("<ul class=\"tagged-pages\">{}</ul>", items));
+    }
+
+    render_page(StatusCode::OK, &html)
+}
+
+/// `GET /api/tags`: every tag on a page the caller may read, with the number of such pages.
+/// Like search, pages whose every section is gated above the caller do not count.
+pub async fn tags_handler(headers: HeaderMap) -> Response {
+    let caller = Caller::from_headers(&headers).unwrap_or_else(Caller::guest);
+    let tagged = match crate::DB
+        .tagged_pages(caller.permissions, &caller.roles)
+        .await
+    {
+        Ok(tagged) => tagged,
+        Err(err) => return tags_error(err),
+    };
+
+    let mut counts: Vec<TagCount> = Vec::new();
+    for (tag, page) in tagged {
+        if !policy::readable(DOCS_ROOT, &page, &caller).await {
+            continue;
+        }
+        match counts.last_mut() {
+            Some(last) if last.tag == tag => last.count += 1,
+            _ => counts.push(TagCount {
+                url: tag_url(&tag),
+                tag,
+                count: 1,
+            }),
+        }
+    }
+
+    (StatusCode::OK, Json(counts)).into_response()
+}
+
+fn tags_error(err: rusqlite::Error) -> Response {
+    eprintln!("Failed to load tags: {}", err);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load tags").into_response()
+}
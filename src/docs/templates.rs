@@ -0,0 +1,63 @@
+//! Page templates: Markdown files under [`TEMPLATES_ROOT`] offered when a page is created.
+//!
+//! A template is copied into the editor as is, and the editor form remembers which one in
+//! a hidden `template` field. Its placeholders (`{{title}}`, `{{date}}` and `{{author}}`)
+//! are filled in by [`fill`] when the new page is first saved from that form.
+
+use chrono::NaiveDate;
+
+use super::{escape_html, page_keys, paths};
+
+/// The templates directory served by the app, relative to the working directory.
+pub const TEMPLATES_ROOT: &str = "templates";
+
+/// Every template under `root` by name, sorted. A missing directory has no templates.
+pub async fn list(root: &str) -> Vec<String> {
+    match page_keys(root).await {
+        Ok(templates) => templates,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => {
+            eprintln!("Failed to list templates under {}: {}", root, err);
+            Vec::new()
+        }
+    }
+}
+
+/// The Markdown of the template `name`, if there is one.
+pub async fn load(root: &str, name: &str) -> Option<String> {
+    let file = paths::page_file(root, name).await?;
+    tokio::fs::read_to_string(file).await.ok()
+}
+
+/// Replaces the placeholders in `contents` for a new page. The title is the last segment
+/// of the page key, since the page has no front matter of its own yet.
+pub fn fill(contents: &str, page: &str, author: &str, date: NaiveDate) -> String {
+    let title = page.rsplit('/').next().unwrap_or(page);
+    contents
+        .replace("{{title}}", title)
+        .replace("{{date}}", &date.format("%Y-%m-%d").to_string())
+        .replace("{{author}}", author)
+}
+
+/// A form that reloads the editor of `uri_path` with one of `templates`.
+pub fn picker(uri_path: &str, templates: &[String], selected: Option<&str>) -> String {
+    let options: String = templates
+        .iter()
+        .map(|name| {
+            format!(
+                "<option value=\"{name}\"{selected}>{name}</option>",
+                name = escape_html(name),
+                selected = if selected == Some(name.as_str()) {
+                    " selected"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect();
+    format!(
+        "<form method=\"get\" action=\"/docs{}\" class=\"template-picker\"><input type=\"hidden\" name=\"edit\" value=\"\"><label>Start from <select name=\"template\"><option value=\"\">Blank page</option>{}</select></label> <button type=\"submit\">Use template</button></form>",
+        escape_html(uri_path),
+        options
+    )
+}
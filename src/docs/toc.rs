@@ -0,0 +1,145 @@
+//! Heading anchors and the table of contents.
+//!
+//! Every rendered heading gets a GitHub-style slug as its `id` plus a permalink. A
+//! paragraph holding nothing but `[[toc]]` is replaced by a table of contents; pages
+//! without one get it at the top once they have enough headings (see
+//! [`super::RenderContext::auto_toc`]). Only headings that survived gating are listed.
+
+use comrak::Anchorizer;
+use comrak::adapters::{HeadingAdapter, HeadingMeta};
+use comrak::nodes::{AstNode, NodeValue};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use super::escape_html;
+
+/// Stands in for the table of contents until every section has been rendered. Raw HTML in
+/// pages is never passed through, so a page cannot fake it.
+pub(super) const PLACEHOLDER: &str = "<!--toc-->\n";
+
+/// A heading as rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    pub slug: String,
+}
+
+/// Renders headings with slug ids and permalinks, remembering them for the table of
+/// contents. One instance is shared by all sections of a page so slugs stay unique.
+#[derive(Default)]
+pub(super) struct HeadingAnchors {
+    state: Mutex<AnchorState>,
+}
+
+#[derive(Default)]
+struct AnchorState {
+    anchorizer: Anchorizer,
+    headings: Vec<Heading>,
+}
+
+impl HeadingAnchors {
+    pub(super) fn into_headings(self) -> Vec<Heading> {
+        self.state
+            .into_inner()
+            .expect("heading state poisoned")
+            .headings
+    }
+}
+
+impl HeadingAdapter for HeadingAnchors {
+    fn enter(
+        &self,
+        output: &mut dyn Write,
+        heading: &HeadingMeta,
+        _sourcepos: Option<comrak::nodes::Sourcepos>,
+    ) -> io::Result<()> {
+        let mut state = self.state.lock().expect("heading state poisoned");
+        let slug = state.anchorizer.anchorize(heading.content.clone());
+        write!(output, "<h{} id=\"{}\">", heading.level, escape_html(&slug))?;
+        state.headings.push(Heading {
+            level: heading.level,
+            text: heading.content.clone(),
+            slug,
+        });
+        Ok(())
+    }
+
+    fn exit(&self, output: &mut dyn Write, heading: &HeadingMeta) -> io::Result<()> {
+        let state = self.state.lock().expect("heading state poisoned");
+        let slug = state
+            .headings
+            .last()
+            .map(|heading| heading.slug.as_str())
+            .unwrap_or_default();
+        writeln!(
+            output,
+            "<a class=\"permalink\" href=\"#{}\" aria-label=\"Permalink\">#</a></h{}>",
+            escape_html(slug),
+            heading.level
+        )
+    }
+}
+
+/// Swaps `[[toc]]` paragraphs for [`PLACEHOLDER`]. Runs before wiki links are resolved so
+/// the marker is not taken for a link to a page called `toc`.
+pub(super) fn mark_placeholders<'a>(root: &'a AstNode<'a>) {
+    let markers: Vec<&AstNode> = root
+        .descendants()
+        .filter(|node| {
+            matches!(node.data.borrow().value, NodeValue::Paragraph)
+                && node.first_child().is_some_and(|child| {
+                    child.next_sibling().is_none()
+                        && matches!(
+                            &child.data.borrow().value,
+                            NodeValue::WikiLink(link) if link.url.trim().eq_ignore_ascii_case("toc")
+                        )
+                })
+        })
+        .collect();
+
+    for node in markers {
+        if let Some(child) = node.first_child() {
+            child.detach();
+        }
+        node.data.borrow_mut().value = NodeValue::Raw(PLACEHOLDER.into());
+    }
+}
+
+/// The table of contents of `headings`, nested by level, or nothing without headings.
+pub fn render(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+
+    let mut html = String::from("<nav class=\"toc\" aria-label=\"Contents\"><ul>");
+    // The heading level of each open list, outermost first.
+    let mut open: Vec<u8> = Vec::new();
+    for heading in headings {
+        match open.last() {
+            None => open.push(heading.level),
+            Some(&level) if heading.level > level => {
+                html.push_str("<ul>");
+                open.push(heading.level);
+            }
+            Some(_) => {
+                html.push_str("</li>");
+                while open.len() > 1 && heading.level <= open[open.len() - 2] {
+                    html.push_str("</ul></li>");
+                    open.pop();
+                }
+            }
+        }
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape_html(&heading.slug),
+            escape_html(&heading.text)
+        ));
+    }
+    html.push_str("</li>");
+    for _ in 1..open.len() {
+        html.push_str("</ul></li>");
+    }
+    html.push_str("</ul></nav>");
+    html
+}
@@ -1,14 +1,23 @@
 use lazy_static::lazy_static;
 
 pub mod app;
+pub mod config;
 pub mod db;
 pub mod docs;
+pub mod mail;
 pub mod user;
 
+use config::Config;
 use db::Database;
+use mail::Mailer;
+use user::keys::KeyRing;
 
 lazy_static! {
     pub static ref DB: Database = Database::new("db.sqlite").unwrap();
+    pub static ref CONFIG: Config =
+        Config::load(config::CONFIG_PATH).unwrap_or_else(|err| panic!("Invalid config: {}", err));
+    pub static ref KEYS: KeyRing =
+        KeyRing::load(&CONFIG.auth).unwrap_or_else(|err| panic!("Invalid token keys: {}", err));
+    pub static ref MAILER: Mailer =
+        Mailer::new(&CONFIG.mail).unwrap_or_else(|err| panic!("Invalid mail settings: {}", err));
 }
-
-pub const SECRET_KEY: &[u8] = include_bytes!("../secret_key");
@@ -0,0 +1,102 @@
+//! Outgoing mail, sent through the transport chosen in the `[mail]` settings (see
+//! [`MailConfig`]).
+
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::stub::AsyncStubTransport;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::{MailConfig, MailTransport};
+
+pub struct Mailer {
+    from: Mailbox,
+    transport: Transport,
+}
+
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(PathBuf),
+    Memory(AsyncStubTransport),
+    Log,
+}
+
+impl Mailer {
+    pub fn new(config: &MailConfig) -> Result<Self, String> {
+        let from = config
+            .from
+            .parse()
+            .map_err(|err| format!("sender \"{}\": {}", config.from, err))?;
+        let transport = match &config.transport {
+            MailTransport::Smtp(source) => {
+                let url = String::from_utf8(source.read()?)
+                    .map_err(|_| "the SMTP URL is not valid UTF-8".to_string())?;
+                let smtp = AsyncSmtpTransport::<Tokio1Executor>::from_url(url.trim())
+                    .map_err(|err| format!("SMTP URL: {}", err))?;
+                Transport::Smtp(smtp.build())
+            }
+            MailTransport::File(dir) => Transport::File(dir.clone()),
+            MailTransport::Memory => Transport::Memory(AsyncStubTransport::new_ok()),
+            MailTransport::Log => Transport::Log,
+        };
+        Ok(Mailer { from, transport })
+    }
+
+    /// Sends a plain-text message.
+    pub async fn send(&self, to: Mailbox, subject: &str, body: String) -> Result<(), String> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|err| err.to_string())?;
+
+        match &self.transport {
+            Transport::Smtp(smtp) => smtp
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Transport::File(dir) => write_message(dir, &message)
+                .await
+                .map_err(|err| format!("{}: {}", dir.display(), err)),
+            Transport::Memory(stub) => stub.send(message).await.map_err(|err| err.to_string()),
+            Transport::Log => {
+                println!(
+                    "Mail not sent, no transport configured:\n{}",
+                    String::from_utf8_lossy(&message.formatted())
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Every message the memory transport has kept, as sent. Other transports keep none.
+    pub async fn sent(&self) -> Vec<String> {
+        match &self.transport {
+            Transport::Memory(stub) => stub
+                .messages()
+                .await
+                .into_iter()
+                .map(|(_, message)| message)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Writes `message` to a new `.eml` file in `dir`, named so files sort by time.
+async fn write_message(dir: &Path, message: &Message) -> std::io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    tokio::fs::create_dir_all(dir).await?;
+    let name = format!(
+        "{}-{}-{}.eml",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    tokio::fs::write(dir.join(name), message.formatted()).await
+}
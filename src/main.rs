@@ -1,10 +1,8 @@
-use axum::Router;
 use std::net::SocketAddr;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::{net::TcpListener, sync::mpsc::Receiver};
-use tower_http::services::ServeDir;
 
-use wiki::{DB, docs::ServeDocs, user};
+use wiki::DB;
 
 #[tokio::main]
 async fn main() {
@@ -13,10 +11,7 @@ async fn main() {
     tokio::spawn(async move {
         let mut input = String::new();
         let mut reader = BufReader::new(tokio::io::stdin());
-        loop {
-            let Ok(n) = reader.read_line(&mut input).await else {
-                break;
-            };
+        while let Ok(n) = reader.read_line(&mut input).await {
             if n == 0 {
                 break; // EOF (I don't think this is possible)
             }
@@ -34,16 +29,15 @@ async fn main() {
         }
     });
 
-    let api_routes = Router::new()
-        .route("/api/login", axum::routing::post(user::login_handler))
-        .route("/api/register", axum::routing::post(user::register_handler));
+    // Fail now rather than on the first login or password reset.
+    lazy_static::initialize(&wiki::KEYS);
+    lazy_static::initialize(&wiki::MAILER);
+    let app = wiki::app::router();
 
-    let static_files = ServeDir::new("frontend");
-
-    let app = Router::new()
-        .merge(api_routes)
-        .nest_service("/docs", ServeDocs::new("docs"))
-        .fallback_service(static_files);
+    match wiki::docs::reindex(&DB, wiki::docs::DOCS_ROOT, &wiki::CONFIG.markdown).await {
+        Ok(count) => println!("Indexed {} docs", count),
+        Err(err) => eprintln!("Failed to index docs: {}", err),
+    }
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     println!("Server running at http://{}", addr);
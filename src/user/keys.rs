@@ -0,0 +1,188 @@
+//! The keys access tokens are signed and verified with, read at startup from the `[auth]`
+//! settings (see [`AuthConfig`]).
+//!
+//! Every token names the key that signed it in its `kid` header. To rotate, add the new
+//! key, point `signing_key` at it and drop the old one once its tokens have expired. With
+//! an EdDSA or RS256 signing key, other services can verify tokens holding only the public
+//! key.
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::config::{AuthConfig, KeyAlgorithm, KeyConfig, KeySource};
+
+/// Where the key comes from when `[auth]` lists none, ahead of [`DEFAULT_KEY_PATH`].
+pub const DEFAULT_KEY_ENV: &str = "WIKI_SECRET_KEY";
+
+/// The HS256 secret used when `[auth]` lists no keys and [`DEFAULT_KEY_ENV`] is unset,
+/// relative to the working directory.
+pub const DEFAULT_KEY_PATH: &str = "secret_key";
+
+/// The `kid` of the key read from [`DEFAULT_KEY_ENV`] or [`DEFAULT_KEY_PATH`].
+pub const DEFAULT_KEY_ID: &str = "default";
+
+struct Key {
+    id: String,
+    algorithm: Algorithm,
+    /// `None` for keys that only verify.
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    /// The secret or private key as read, which keys HMACs that never leave the server.
+    material: Vec<u8>,
+}
+
+pub struct KeyRing {
+    keys: Vec<Key>,
+    signing: usize,
+}
+
+impl KeyRing {
+    /// Reads every key `auth` lists, or the default key when it lists none.
+    pub fn load(auth: &AuthConfig) -> Result<Self, String> {
+        let keys = if auth.keys.is_empty() {
+            let source = match std::env::var(DEFAULT_KEY_ENV) {
+                Ok(_) => KeySource::Env(DEFAULT_KEY_ENV.into()),
+                Err(_) => KeySource::Path(DEFAULT_KEY_PATH.into()),
+            };
+            vec![load_key(&KeyConfig {
+                id: DEFAULT_KEY_ID.into(),
+                algorithm: KeyAlgorithm::HS256,
+                secret: Some(source),
+                private_key: None,
+                public_key: None,
+            })?]
+        } else {
+            auth.keys.iter().map(load_key).collect::<Result<_, _>>()?
+        };
+
+        for (index, key) in keys.iter().enumerate() {
+            if keys[..index].iter().any(|other| other.id == key.id) {
+                return Err(format!("key \"{}\" is listed twice", key.id));
+            }
+        }
+
+        let signing = match &auth.signing_key {
+            Some(id) => {
+                let index = keys
+                    .iter()
+                    .position(|key| &key.id == id)
+                    .ok_or_else(|| format!("signing key \"{}\" is not listed", id))?;
+                if keys[index].encoding.is_none() {
+                    return Err(format!("signing key \"{}\" has no private key", id));
+                }
+                index
+            }
+            None => keys
+                .iter()
+                .position(|key| key.encoding.is_some())
+                .ok_or("no key can sign tokens")?,
+        };
+
+        Ok(KeyRing { keys, signing })
+    }
+
+    /// Signs `claims` with the signing key.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> jsonwebtoken::errors::Result<String> {
+        let key = &self.keys[self.signing];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.id.clone());
+        let encoding = key.encoding.as_ref().expect("the signing key can sign");
+        jsonwebtoken::encode(&header, claims, encoding)
+    }
+
+    /// The claims of `token` if a listed key signed it and it has not expired. Tokens
+    /// without a `kid` predate key ids and are checked against the signing key.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Option<T> {
+        let header = jsonwebtoken::decode_header(token).ok()?;
+        let key = match &header.kid {
+            Some(kid) => self.keys.iter().find(|key| &key.id == kid)?,
+            None => &self.keys[self.signing],
+        };
+        jsonwebtoken::decode(token, &key.decoding, &Validation::new(key.algorithm))
+            .ok()
+            .map(|data| data.claims)
+    }
+
+    /// A secret for HMACs the server checks itself, such as CSRF tokens. Changes when the
+    /// signing key does.
+    pub fn hmac_secret(&self) -> &[u8] {
+        &self.keys[self.signing].material
+    }
+}
+
+fn load_key(config: &KeyConfig) -> Result<Key, String> {
+    let id = &config.id;
+    let read = |source: &KeySource| {
+        source
+            .read()
+            .map_err(|err| format!("key \"{}\": {}", id, err))
+    };
+    let invalid = |err: jsonwebtoken::errors::Error| format!("key \"{}\": {}", id, err);
+
+    if config.algorithm == KeyAlgorithm::HS256 {
+        if config.private_key.is_some() || config.public_key.is_some() {
+            return Err(format!("key \"{}\": HS256 keys take a `secret`", id));
+        }
+        let secret = read(
+            config
+                .secret
+                .as_ref()
+                .ok_or_else(|| format!("key \"{}\": HS256 keys need a `secret`", id))?,
+        )?;
+        if secret.is_empty() {
+            return Err(format!("key \"{}\": the secret is empty", id));
+        }
+        return Ok(Key {
+            id: id.clone(),
+            algorithm: Algorithm::HS256,
+            encoding: Some(EncodingKey::from_secret(&secret)),
+            decoding: DecodingKey::from_secret(&secret),
+            material: secret,
+        });
+    }
+
+    if config.secret.is_some() {
+        return Err(format!(
+            "key \"{}\": {:?} keys take a `private_key` and `public_key`",
+            id, config.algorithm
+        ));
+    }
+    let public_key = read(config.public_key.as_ref().ok_or_else(|| {
+        format!(
+            "key \"{}\": {:?} keys need a `public_key`",
+            id, config.algorithm
+        )
+    })?)?;
+    let private_key = config.private_key.as_ref().map(read).transpose()?;
+
+    let (algorithm, decoding, encoding) = match config.algorithm {
+        KeyAlgorithm::EdDSA => (
+            Algorithm::EdDSA,
+            DecodingKey::from_ed_pem(&public_key).map_err(invalid)?,
+            private_key
+                .as_deref()
+                .map(EncodingKey::from_ed_pem)
+                .transpose()
+                .map_err(invalid)?,
+        ),
+        KeyAlgorithm::RS256 => (
+            Algorithm::RS256,
+            DecodingKey::from_rsa_pem(&public_key).map_err(invalid)?,
+            private_key
+                .as_deref()
+                .map(EncodingKey::from_rsa_pem)
+                .transpose()
+                .map_err(invalid)?,
+        ),
+        KeyAlgorithm::HS256 => unreachable!("handled above"),
+    };
+
+    Ok(Key {
+        id: id.clone(),
+        algorithm,
+        encoding,
+        decoding,
+        material: private_key.unwrap_or_default(),
+    })
+}
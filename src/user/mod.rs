@@ -1,7 +1,29 @@
-use axum::{Json, response::IntoResponse};
+use axum::Json;
+use axum::extract::Query;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{AppendHeaders, IntoResponse};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::SECRET_KEY;
+use crate::KEYS;
+use crate::db::Rotation;
+
+pub mod keys;
+pub mod password;
+
+/// How long an access token is accepted, in seconds. Access tokens cannot be revoked, so
+/// this bounds how long one outlives a logout.
+pub const ACCESS_TOKEN_LIFETIME: u64 = 15 * 60;
+
+/// How long a session survives without being refreshed, in seconds.
+pub const REFRESH_TOKEN_LIFETIME: u64 = 30 * 24 * 3600;
+
+/// The HttpOnly cookie carrying the access token, accepted wherever a bearer token is.
+pub const SESSION_COOKIE: &str = "wiki_session";
+
+/// The HttpOnly cookie carrying the refresh token. Browsers only send it to `/api`, where
+/// `/api/refresh` and `/api/logout` read it when the body has no token.
+pub const REFRESH_COOKIE: &str = "wiki_refresh";
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
@@ -9,10 +31,44 @@ pub struct LoginRequest {
     password: String,
 }
 
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    username: String,
+    password: String,
+    /// Where password reset links are mailed. Accounts without one cannot reset.
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LogoutRequest {
+    refresh_token: Option<String>,
+    /// End every session of the token's user, not just this one.
+    #[serde(default)]
+    everywhere: bool,
+}
+
+/// Lets browsers, which log out with an empty body and their refresh cookie, ask for
+/// `/api/logout?everywhere=true`.
+#[derive(Deserialize)]
+pub struct LogoutQuery {
+    #[serde(default)]
+    everywhere: bool,
+}
+
 #[derive(Serialize)]
 struct AuthResponse {
     token: String,
+    /// Seconds until `token` expires.
+    expires_in: u64,
+    /// Exchanged at `/api/refresh` for a new pair of tokens; good for one use.
+    refresh_token: String,
     privileges: i32,
+    roles: Vec<String>,
 }
 
 pub async fn login_handler(Json(payload): Json<LoginRequest>) -> impl IntoResponse {
@@ -27,22 +83,24 @@ pub async fn login_handler(Json(payload): Json<LoginRequest>) -> impl IntoRespon
             .into_response();
     };
 
-    let auth_token = create_jwt(payload.username.as_str(), privilege).unwrap();
+    auth_response(payload.username.as_str(), privilege).await
+}
 
-    let response = AuthResponse {
-        token: auth_token,
-        privileges: privilege,
+pub async fn register_handler(Json(payload): Json<RegisterRequest>) -> impl IntoResponse {
+    let email = match payload.email.as_deref().map(password::parse_email) {
+        Some(None) => {
+            return (StatusCode::BAD_REQUEST, "Invalid email address").into_response();
+        }
+        Some(Some(address)) => Some(address.to_string()),
+        None => None,
     };
 
-    (axum::http::StatusCode::OK, Json(response)).into_response()
-}
-
-pub async fn register_handler(Json(payload): Json<LoginRequest>) -> impl IntoResponse {
     let Ok(_) = crate::DB
-        .add_user(
+        .add_user_with_email(
             payload.username.as_str(),
             payload.password.as_str(),
             if payload.username == "root" { 0 } else { 1 },
+            email.as_deref(),
         )
         .await
     else {
@@ -64,63 +122,283 @@ pub async fn register_handler(Json(payload): Json<LoginRequest>) -> impl IntoRes
             .into_response();
     };
 
-    let auth_token = create_jwt(payload.username.as_str(), privilege).unwrap();
+    auth_response(payload.username.as_str(), privilege).await
+}
+
+/// Opens a session for a user who just proved who they are.
+async fn auth_response(username: &str, privileges: i32) -> axum::response::Response {
+    let refresh_token = random_token();
+    let session = match crate::DB
+        .create_session(username, &hash_token(&refresh_token), refresh_expiry())
+        .await
+    {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("Failed to open a session for {}: {}", username, err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to open a session",
+            )
+                .into_response();
+        }
+    };
+
+    token_response(session, username, privileges, refresh_token).await
+}
+
+/// Exchanges a refresh token, from the body or the refresh cookie, for a new access token
+/// and a new refresh token. A refresh token that was already exchanged ends its session,
+/// since someone kept a copy.
+pub async fn refresh_handler(
+    headers: HeaderMap,
+    payload: Option<Json<RefreshRequest>>,
+) -> impl IntoResponse {
+    let Some(presented) = payload
+        .and_then(|Json(payload)| payload.refresh_token)
+        .or_else(|| cookie(&headers, REFRESH_COOKIE).map(str::to_string))
+    else {
+        return (StatusCode::UNAUTHORIZED, "Invalid or expired refresh token").into_response();
+    };
+
+    let refresh_token = random_token();
+    let rotation = crate::DB
+        .rotate_session(
+            &hash_token(&presented),
+            &hash_token(&refresh_token),
+            refresh_expiry(),
+        )
+        .await;
 
-    #[derive(Serialize)]
-    struct LoginResponse {
-        token: String,
-        privileges: i32,
+    match rotation {
+        Ok(Rotation::Rotated {
+            session,
+            username,
+            privileges,
+        }) => token_response(session, &username, privileges, refresh_token).await,
+        Ok(Rotation::Reused) => {
+            eprintln!("A refresh token was used twice; its session has been ended");
+            (StatusCode::UNAUTHORIZED, "Invalid or expired refresh token").into_response()
+        }
+        Ok(Rotation::Invalid) => {
+            (StatusCode::UNAUTHORIZED, "Invalid or expired refresh token").into_response()
+        }
+        Err(err) => {
+            eprintln!("Failed to refresh a session: {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to refresh session",
+            )
+                .into_response()
+        }
     }
+}
 
-    let response = LoginResponse {
-        token: auth_token,
-        privileges: privilege,
+/// Ends the session of a refresh token, or with `everywhere` every session of its user,
+/// and clears the session cookies. Access tokens already handed out stay valid until they
+/// expire.
+pub async fn logout_handler(
+    headers: HeaderMap,
+    Query(query): Query<LogoutQuery>,
+    payload: Option<Json<LogoutRequest>>,
+) -> impl IntoResponse {
+    let (refresh_token, everywhere) = match payload {
+        Some(Json(payload)) => (
+            payload.refresh_token,
+            payload.everywhere || query.everywhere,
+        ),
+        None => (None, query.everywhere),
+    };
+    let Some(refresh_token) =
+        refresh_token.or_else(|| cookie(&headers, REFRESH_COOKIE).map(str::to_string))
+    else {
+        return (StatusCode::UNAUTHORIZED, "Invalid or expired refresh token").into_response();
+    };
+
+    let username = match crate::DB.end_session(&hash_token(&refresh_token)).await {
+        Ok(Some(username)) => username,
+        Ok(None) => {
+            return (StatusCode::UNAUTHORIZED, "Invalid or expired refresh token").into_response();
+        }
+        Err(err) => {
+            eprintln!("Failed to end a session: {}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to log out").into_response();
+        }
     };
 
-    let response = serde_json::to_string(&response).unwrap();
+    if everywhere && let Err(err) = crate::DB.end_all_sessions(&username).await {
+        eprintln!("Failed to end the sessions of {}: {}", username, err);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to log out").into_response();
+    }
 
-    (axum::http::StatusCode::OK, response).into_response()
+    (
+        StatusCode::NO_CONTENT,
+        AppendHeaders([
+            (
+                header::SET_COOKIE,
+                session_cookie(SESSION_COOKIE, "", "/", 0),
+            ),
+            (
+                header::SET_COOKIE,
+                session_cookie(REFRESH_COOKIE, "", "/api", 0),
+            ),
+        ]),
+    )
+        .into_response()
 }
 
-#[derive(Deserialize, Serialize)]
-struct JwtClaims {
-    sub: String,
+/// Mints an access token to go with `refresh_token`, returned in the body for API clients
+/// and as cookies for browsers.
+async fn token_response(
+    session: i64,
+    username: &str,
     privileges: i32,
-    exp: u64,
+    refresh_token: String,
+) -> axum::response::Response {
+    let roles = match crate::DB.user_roles(username).await {
+        Ok(roles) => roles,
+        Err(err) => {
+            eprintln!("Failed to load roles of {}: {}", username, err);
+            return (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to load roles",
+            )
+                .into_response();
+        }
+    };
+
+    let auth_token = create_jwt(session, username, privileges, roles.clone()).unwrap();
+    let cookies = AppendHeaders([
+        (
+            header::SET_COOKIE,
+            session_cookie(SESSION_COOKIE, &auth_token, "/", ACCESS_TOKEN_LIFETIME),
+        ),
+        (
+            header::SET_COOKIE,
+            session_cookie(
+                REFRESH_COOKIE,
+                &refresh_token,
+                "/api",
+                REFRESH_TOKEN_LIFETIME,
+            ),
+        ),
+    ]);
+
+    let response = AuthResponse {
+        token: auth_token,
+        expires_in: ACCESS_TOKEN_LIFETIME,
+        refresh_token,
+        privileges,
+        roles,
+    };
+
+    (axum::http::StatusCode::OK, cookies, Json(response)).into_response()
 }
 
-fn create_jwt(username: &str, privileges: i32) -> Result<String, jsonwebtoken::errors::Error> {
-    use jsonwebtoken::{EncodingKey, Header, encode};
+#[derive(Deserialize, Serialize)]
+pub struct JwtClaims {
+    pub sub: String,
+    pub privileges: i32,
+    /// Tokens minted before roles existed carry none.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// The session the token was issued for. Tokens minted outside a login carry none.
+    #[serde(default)]
+    pub sid: Option<i64>,
+    pub exp: u64,
+}
 
-    let expiration = get_current_timestamp() + 24 * 3600; // 24 hours from now
+fn create_jwt(
+    session: i64,
+    username: &str,
+    privileges: i32,
+    roles: Vec<String>,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let expiration = get_current_timestamp() + ACCESS_TOKEN_LIFETIME;
 
     let claims = JwtClaims {
         sub: username.to_string(),
         privileges,
+        roles,
+        sid: Some(session),
         exp: expiration,
     };
 
-    let encoding_key = EncodingKey::from_secret(SECRET_KEY);
-    encode(&Header::default(), &claims, &encoding_key)
+    KEYS.encode(&claims)
+}
+
+/// The value of the cookie `name` in a request's `Cookie` headers.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+}
+
+/// An HttpOnly cookie scoped to `path`. The access token may be sent along when following
+/// a link from elsewhere, so it is `SameSite=Lax`; the refresh token never needs to be.
+fn session_cookie(name: &str, value: &str, path: &str, max_age: u64) -> HeaderValue {
+    let same_site = if name == REFRESH_COOKIE {
+        "Strict"
+    } else {
+        "Lax"
+    };
+    let secure = if crate::CONFIG.auth.secure_cookies {
+        "; Secure"
+    } else {
+        ""
+    };
+    HeaderValue::from_str(&format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite={}{}",
+        name, value, path, max_age, same_site, secure
+    ))
+    .expect("tokens are valid header values")
+}
+
+/// 32 random bytes, hex-encoded.
+fn random_token() -> String {
+    use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Refresh and reset tokens are random, so a plain digest is enough to keep them out
+/// of the database.
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn refresh_expiry() -> i64 {
+    (get_current_timestamp() + REFRESH_TOKEN_LIFETIME) as i64
 }
 
 pub fn get_jwt_perms(jwt: &str) -> Option<i32> {
-    use jsonwebtoken::{DecodingKey, Validation, decode};
+    decode_jwt(jwt).map(|claims| claims.privileges)
+}
 
+/// Validates `jwt` and returns its claims. The literal token `guest` maps to an
+/// anonymous level-1 user.
+pub fn decode_jwt(jwt: &str) -> Option<JwtClaims> {
     if jwt == "guest" {
-        return Some(1);
+        return Some(JwtClaims {
+            sub: "guest".into(),
+            privileges: 1,
+            roles: Vec::new(),
+            sid: None,
+            exp: 0,
+        });
     }
 
-    let decoding_key = DecodingKey::from_secret(SECRET_KEY);
-    let validation = Validation::default();
-
-    match decode::<JwtClaims>(jwt, &decoding_key, &validation) {
-        Ok(token_data) => {
-            let claims = token_data.claims;
-            Some(claims.privileges)
-        }
-        Err(_) => None,
-    }
+    KEYS.decode(jwt)
 }
 
 pub fn get_current_timestamp() -> u64 {
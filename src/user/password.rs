@@ -0,0 +1,100 @@
+//! Password resets. `/api/password/forgot` mails a single-use link to the address an
+//! account registered with, and `/api/password/reset` sets a new password with its token.
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use lettre::Address;
+use lettre::message::Mailbox;
+use serde::Deserialize;
+
+use super::{get_current_timestamp, hash_token, random_token};
+use crate::mail::Mailer;
+
+/// How long a reset link works, in seconds.
+pub const PASSWORD_RESET_LIFETIME: u64 = 60 * 60;
+
+#[derive(Deserialize)]
+pub struct ForgotRequest {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetRequest {
+    token: String,
+    password: String,
+}
+
+/// Mails a reset link if an account registered with the address. The answer is `202
+/// Accepted` either way and the mail goes out in the background, so callers cannot tell
+/// which addresses are registered.
+pub async fn forgot_handler(
+    State(mailer): State<&'static Mailer>,
+    Json(payload): Json<ForgotRequest>,
+) -> impl IntoResponse {
+    let Some(address) = parse_email(&payload.email) else {
+        return (StatusCode::BAD_REQUEST, "Invalid email address").into_response();
+    };
+
+    let token = random_token();
+    let expires_at = (get_current_timestamp() + PASSWORD_RESET_LIFETIME) as i64;
+    let username = match crate::DB
+        .create_password_reset(address.as_ref(), &hash_token(&token), expires_at)
+        .await
+    {
+        Ok(Some(username)) => username,
+        Ok(None) => return StatusCode::ACCEPTED.into_response(),
+        Err(err) => {
+            eprintln!("Failed to start a password reset: {}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to start password reset",
+            )
+                .into_response();
+        }
+    };
+
+    let body = format!(
+        "Hello {},\n\nSomeone asked to reset the password of your wiki account. To choose a\nnew one, open this link within an hour:\n\n{}?token={}\n\nIf that was not you, ignore this message and your password stays as it is.\n",
+        username,
+        crate::CONFIG.mail.reset_url,
+        token
+    );
+    tokio::spawn(async move {
+        let to = Mailbox::new(None, address);
+        if let Err(err) = mailer.send(to, "Reset your wiki password", body).await {
+            eprintln!("Failed to mail a password reset to {}: {}", username, err);
+        }
+    });
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Sets a new password with a reset token and logs the account out everywhere.
+pub async fn reset_handler(Json(payload): Json<ResetRequest>) -> impl IntoResponse {
+    if payload.password.is_empty() {
+        return (StatusCode::BAD_REQUEST, "The new password is empty").into_response();
+    }
+
+    match crate::DB
+        .reset_password(&hash_token(&payload.token), &payload.password)
+        .await
+    {
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None) => (StatusCode::BAD_REQUEST, "Invalid or expired reset token").into_response(),
+        Err(err) => {
+            eprintln!("Failed to reset a password: {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to reset password",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// A mail address in the form it is stored and looked up in: trimmed and lowercased.
+pub fn parse_email(input: &str) -> Option<Address> {
+    input.trim().to_lowercase().parse().ok()
+}
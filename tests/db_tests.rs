@@ -111,6 +111,49 @@ async fn set_user_privileges_updates_role() {
     db.close().await;
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn set_user_privileges_replaces_level_role() {
+    use wiki::db::{ROOT_ROLE, Requirement};
+
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+
+    db.add_user("erin", "password", 0)
+        .await
+        .expect("add_user failed");
+    db.grant_role("erin", "finance")
+        .await
+        .expect("grant_role failed");
+    assert_eq!(
+        db.user_roles("erin").await.unwrap(),
+        vec!["finance".to_string(), ROOT_ROLE.to_string()]
+    );
+
+    let conn = Connection::open(&path).expect("open connection");
+    let user_id: i32 = conn
+        .query_row(
+            "SELECT id FROM users WHERE username = ?1",
+            [&"erin"],
+            |row| row.get(0),
+        )
+        .expect("query user id");
+    db.set_user_privileges(user_id, 3)
+        .await
+        .expect("set_user_privileges failed");
+
+    let roles = db.user_roles("erin").await.unwrap();
+    assert_eq!(roles, vec!["finance".to_string(), "level-3".to_string()]);
+    assert!(!Requirement::role(ROOT_ROLE).allows(3, &roles));
+    assert!(Requirement::role("finance").allows(3, &roles));
+
+    let doc = "Public\n\n!@root\nRoot only\n";
+    let rendered = wiki::docs::render_markdown(doc, 3, &roles, &Default::default());
+    assert!(rendered.contains("Public"));
+    assert!(!rendered.contains("Root only"));
+
+    db.close().await;
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn login_with_stale_privileges_triggers_verification() {
     let (_dir, path) = temp_db_path();
@@ -152,3 +195,411 @@ async fn login_with_stale_privileges_triggers_verification() {
     wiki::db::testing::clear_verification_probe();
     db.close().await;
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn revisions_are_listed_newest_first_per_page() {
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+
+    let first = db
+        .add_revision("guides/setup", "alice", "initial", "v1")
+        .await
+        .expect("add_revision failed");
+    db.add_revision("other", "bob", "", "unrelated")
+        .await
+        .expect("add_revision failed");
+    let second = db
+        .add_revision("guides/setup", "bob", "tweak", "v2")
+        .await
+        .expect("add_revision failed");
+
+    let revisions = db
+        .revisions("guides/setup")
+        .await
+        .expect("revisions failed");
+    let ids: Vec<i64> = revisions.iter().map(|r| r.id).collect();
+    assert_eq!(ids, vec![second, first]);
+    assert_eq!(revisions[0].author, "bob");
+    assert_eq!(revisions[0].content, "v2");
+    assert_eq!(revisions[1].summary, "initial");
+
+    let fetched = db.revision(first).await.expect("revision failed");
+    assert_eq!(fetched.map(|r| r.content), Some("v1".to_string()));
+    assert!(db.revision(9999).await.expect("revision failed").is_none());
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn search_only_matches_sections_within_privileges() {
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+
+    db.index_page(
+        "recipes/pie",
+        vec![
+            (0.into(), "Apple pie needs cinnamon\n".into()),
+            (3.into(), "Secret ingredient is cardamom\n".into()),
+        ],
+    )
+    .await
+    .expect("index_page failed");
+    db.index_page("recipes/tart", vec![(0.into(), "A cinnamon tart\n".into())])
+        .await
+        .expect("index_page failed");
+
+    let hits = db
+        .search("cinnamon", 1, &[], 10)
+        .await
+        .expect("search failed");
+    let mut pages: Vec<&str> = hits.iter().map(|hit| hit.page.as_str()).collect();
+    pages.sort();
+    assert_eq!(pages, vec!["recipes/pie", "recipes/tart"]);
+
+    assert!(db.search("cardamom", 1, &[], 10).await.unwrap().is_empty());
+    assert_eq!(db.search("cardamom", 3, &[], 10).await.unwrap().len(), 1);
+    assert_eq!(db.search("cardamom", 0, &[], 10).await.unwrap().len(), 1);
+
+    // Query syntax in user input is treated as plain words.
+    assert!(db.search("\"cinnamon OR (", 1, &[], 10).await.is_ok());
+
+    db.replace_index(vec![(
+        "recipes/tart".into(),
+        vec![(0.into(), "A cinnamon tart\n".into())],
+    )])
+    .await
+    .expect("replace_index failed");
+    let hits = db.search("cinnamon", 1, &[], 10).await.unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].page, "recipes/tart");
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn backlinks_respect_section_levels() {
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+
+    db.replace_links(
+        "intro",
+        vec![(0.into(), "faq".into()), (0.into(), "intro".into())],
+    )
+    .await
+    .expect("replace_links failed");
+    db.replace_links("ops/secrets", vec![(4.into(), "faq".into())])
+        .await
+        .expect("replace_links failed");
+
+    assert_eq!(db.backlinks("faq", 1, &[]).await.unwrap(), vec!["intro"]);
+    assert_eq!(
+        db.backlinks("faq", 4, &[]).await.unwrap(),
+        vec!["intro", "ops/secrets"]
+    );
+    assert_eq!(
+        db.backlinks("faq", 0, &[]).await.unwrap(),
+        vec!["intro", "ops/secrets"]
+    );
+    // Self-links are not backlinks.
+    assert!(db.backlinks("intro", 0, &[]).await.unwrap().is_empty());
+
+    db.replace_all_links(vec![("ops/secrets".into(), vec![(4.into(), "faq".into())])])
+        .await
+        .expect("replace_all_links failed");
+    assert!(db.backlinks("faq", 1, &[]).await.unwrap().is_empty());
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn roles_are_granted_revoked_and_searched() {
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+
+    db.add_user("erin", "password", 3)
+        .await
+        .expect("add_user failed");
+    assert_eq!(db.user_roles("erin").await.unwrap(), vec!["level-3"]);
+
+    db.grant_role("erin", "recipes")
+        .await
+        .expect("grant failed");
+    db.grant_role("erin", "recipes")
+        .await
+        .expect("grant is idempotent");
+    assert!(db.grant_role("erin", "Not A Role").await.is_err());
+    assert!(db.grant_role("nobody", "recipes").await.is_err());
+    assert_eq!(
+        db.user_roles("erin").await.unwrap(),
+        vec!["level-3", "recipes"]
+    );
+
+    db.index_page(
+        "kitchen",
+        vec![(
+            wiki::db::Requirement::role("recipes"),
+            "Secret sauce\n".into(),
+        )],
+    )
+    .await
+    .expect("index_page failed");
+    let roles = db.user_roles("erin").await.unwrap();
+    assert_eq!(db.search("sauce", 3, &roles, 10).await.unwrap().len(), 1);
+    assert!(db.search("sauce", 3, &[], 10).await.unwrap().is_empty());
+
+    db.revoke_role("erin", "recipes")
+        .await
+        .expect("revoke failed");
+    assert_eq!(db.user_roles("erin").await.unwrap(), vec!["level-3"]);
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn existing_privilege_levels_migrate_onto_roles() {
+    let (_dir, path) = temp_db_path();
+    {
+        let conn = Connection::open(&path).expect("open connection");
+        conn.execute_batch(
+            "CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                password TEXT NOT NULL,
+                privileges INTEGER NOT NULL,
+                privileges_last_updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                patreon_id TEXT,
+                patreon_refresh_token TEXT
+            );
+            INSERT INTO users (username, password, privileges) VALUES ('root', 'x', 0);
+            INSERT INTO users (username, password, privileges) VALUES ('patron', 'x', 2);
+            CREATE TABLE page_links (
+                source TEXT NOT NULL,
+                target TEXT NOT NULL,
+                required_level INTEGER NOT NULL
+            );
+            INSERT INTO page_links VALUES ('intro', 'faq', 0);",
+        )
+        .expect("seed legacy schema");
+    }
+
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+    assert_eq!(db.user_roles("root").await.unwrap(), vec!["root"]);
+    assert_eq!(db.user_roles("patron").await.unwrap(), vec!["level-2"]);
+    assert_eq!(db.backlinks("faq", 1, &[]).await.unwrap(), vec!["intro"]);
+
+    // Revoked roles stay revoked when the database is opened again.
+    db.revoke_role("patron", "level-2").await.unwrap();
+    db.close().await;
+    let db = Database::new(path.to_str().unwrap()).expect("failed to reopen db");
+    assert!(db.user_roles("patron").await.unwrap().is_empty());
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn page_tags_are_replaced_and_listed() {
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+
+    db.replace_tags("pie", vec!["baking".into(), "dessert".into()])
+        .await
+        .expect("replace_tags failed");
+    db.replace_tags("bread", vec!["baking".into()])
+        .await
+        .expect("replace_tags failed");
+
+    assert_eq!(
+        db.pages_with_tag("baking", 1, &[]).await.unwrap(),
+        vec!["bread", "pie"]
+    );
+    assert_eq!(
+        db.tagged_pages(1, &[]).await.unwrap(),
+        vec![
+            ("baking".to_string(), "bread".to_string()),
+            ("baking".to_string(), "pie".to_string()),
+            ("dessert".to_string(), "pie".to_string()),
+        ]
+    );
+
+    // Pages whose indexed text is all gated above the reader are left out.
+    db.index_page("pie", vec![(3.into(), "secret filling".into())])
+        .await
+        .unwrap();
+    assert_eq!(
+        db.pages_with_tag("baking", 1, &[]).await.unwrap(),
+        vec!["bread"]
+    );
+    assert_eq!(
+        db.pages_with_tag("baking", 3, &[]).await.unwrap(),
+        vec!["bread", "pie"]
+    );
+    assert_eq!(
+        db.tagged_pages(1, &[]).await.unwrap(),
+        vec![("baking".to_string(), "bread".to_string())]
+    );
+
+    db.replace_tags("pie", vec![]).await.unwrap();
+    assert_eq!(
+        db.pages_with_tag("dessert", 1, &[]).await.unwrap(),
+        Vec::<String>::new()
+    );
+
+    db.replace_all_tags(vec![("cake".into(), vec!["dessert".into()])])
+        .await
+        .expect("replace_all_tags failed");
+    assert_eq!(
+        db.pages_with_tag("baking", 1, &[]).await.unwrap(),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        db.pages_with_tag("dessert", 1, &[]).await.unwrap(),
+        vec!["cake"]
+    );
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn sessions_rotate_and_end_on_reuse() {
+    use wiki::db::Rotation;
+
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+    db.add_user("alice", "password", 3).await.unwrap();
+    let later = chrono::Utc::now().timestamp() + 3600;
+
+    let session = db.create_session("alice", "first", later).await.unwrap();
+    assert_eq!(
+        db.rotate_session("first", "second", later).await.unwrap(),
+        Rotation::Rotated {
+            session,
+            username: "alice".into(),
+            privileges: 3,
+        }
+    );
+
+    // Replaying the exchanged token ends the session, so the new token dies with it.
+    assert_eq!(
+        db.rotate_session("first", "third", later).await.unwrap(),
+        Rotation::Reused
+    );
+    assert_eq!(
+        db.rotate_session("second", "fourth", later).await.unwrap(),
+        Rotation::Invalid
+    );
+
+    // Expired sessions cannot be refreshed or ended.
+    let earlier = chrono::Utc::now().timestamp() - 1;
+    db.create_session("alice", "stale", earlier).await.unwrap();
+    assert_eq!(
+        db.rotate_session("stale", "fresh", later).await.unwrap(),
+        Rotation::Invalid
+    );
+    db.create_session("alice", "stale", earlier).await.unwrap();
+    assert_eq!(db.end_session("stale").await.unwrap(), None);
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn sessions_end_one_at_a_time_or_all_at_once() {
+    use wiki::db::Rotation;
+
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+    db.add_user("alice", "password", 1).await.unwrap();
+    db.add_user("bob", "password", 1).await.unwrap();
+    let later = chrono::Utc::now().timestamp() + 3600;
+    for (username, token) in [
+        ("alice", "laptop"),
+        ("alice", "phone"),
+        ("alice", "tablet"),
+        ("bob", "desk"),
+    ] {
+        db.create_session(username, token, later).await.unwrap();
+    }
+
+    assert_eq!(
+        db.end_session("laptop").await.unwrap(),
+        Some("alice".into())
+    );
+    assert_eq!(db.end_session("laptop").await.unwrap(), None);
+    assert_eq!(db.end_all_sessions("alice").await.unwrap(), 2);
+    assert_eq!(
+        db.rotate_session("phone", "phone-2", later).await.unwrap(),
+        Rotation::Invalid
+    );
+    assert!(matches!(
+        db.rotate_session("desk", "desk-2", later).await.unwrap(),
+        Rotation::Rotated { .. }
+    ));
+
+    db.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn password_resets_are_single_use_and_expire() {
+    let (_dir, path) = temp_db_path();
+    let db = Database::new(path.to_str().unwrap()).expect("failed to create db");
+    db.add_user_with_email("alice", "old password", 1, Some("alice@example.com"))
+        .await
+        .unwrap();
+    db.add_user("bob", "password", 1).await.unwrap();
+    assert!(
+        db.add_user_with_email("mallory", "password", 1, Some("alice@example.com"))
+            .await
+            .is_err()
+    );
+    let now = chrono::Utc::now().timestamp();
+    db.create_session("alice", "laptop", now + 3600)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        db.create_password_reset("nobody@example.com", "unknown", now + 3600)
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        db.create_password_reset("alice@example.com", "stale", now - 1)
+            .await
+            .unwrap(),
+        Some("alice".into())
+    );
+    assert_eq!(
+        db.reset_password("stale", "new password").await.unwrap(),
+        None
+    );
+
+    // A newer link replaces the older one.
+    db.create_password_reset("alice@example.com", "first", now + 3600)
+        .await
+        .unwrap();
+    db.create_password_reset("alice@example.com", "second", now + 3600)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.reset_password("first", "new password").await.unwrap(),
+        None
+    );
+    assert_eq!(
+        db.reset_password("second", "new password").await.unwrap(),
+        Some("alice".into())
+    );
+    assert_eq!(db.reset_password("second", "again").await.unwrap(), None);
+
+    assert_eq!(db.login("alice", "old password").await.unwrap(), None);
+    assert_eq!(db.login("alice", "new password").await.unwrap(), Some(1));
+    assert_eq!(db.end_session("laptop").await.unwrap(), None);
+
+    assert!(db.set_email("bob", Some("bob@example.com")).await.unwrap());
+    assert!(!db.set_email("nobody", Some("x@example.com")).await.unwrap());
+    assert_eq!(
+        db.create_password_reset("bob@example.com", "bob", now + 3600)
+            .await
+            .unwrap(),
+        Some("bob".into())
+    );
+
+    db.close().await;
+}
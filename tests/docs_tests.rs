@@ -22,6 +22,117 @@ fn parse_markdown_returns_placeholder_when_everything_hidden() {
     assert_eq!(rendered, "Page requires higher privileges, try logging in.");
 }
 
+#[test]
+fn parse_markdown_returns_to_public_content_after_end_marker() {
+    let doc = "Intro\n\n!3\nRestricted section\n!end\nOutro\n";
+
+    let rendered = parse_markdown(doc, 1);
+
+    assert!(rendered.contains("<p>Intro</p>"));
+    assert!(!rendered.contains("Restricted section"));
+    assert!(rendered.contains("<p>Outro</p>"));
+}
+
+#[test]
+fn parse_markdown_nests_block_gates() {
+    let doc = "!2 {\nStaff notes\n!4 {\nAdmin notes\n!}\nMore staff notes\n!}\nEveryone\n";
+
+    let staff = parse_markdown(doc, 2);
+    assert!(staff.contains("Staff notes"));
+    assert!(!staff.contains("Admin notes"));
+    assert!(staff.contains("More staff notes"));
+    assert!(staff.contains("Everyone"));
+
+    let admin = parse_markdown(doc, 4);
+    assert!(admin.contains("Admin notes"));
+
+    let guest = parse_markdown(doc, 1);
+    assert!(!guest.contains("notes"));
+    assert!(guest.contains("Everyone"));
+}
+
+#[test]
+fn parse_markdown_leaves_images_and_code_fences_alone() {
+    let doc = "![logo](/logo.png)\n\n```\n!3\n```\nAfter\n";
+
+    let rendered = parse_markdown(doc, 1);
+
+    assert!(rendered.contains("<img src=\"/logo.png\" alt=\"logo\">"));
+    assert!(rendered.contains("<code><span class=\"hl-text hl-plain\">!3\n</span></code>"));
+    assert!(rendered.contains("<p>After</p>"));
+}
+
+#[test]
+fn parse_markdown_applies_inline_gates() {
+    let doc = "Call {!3 555-0100 or }the front desk.\n\n- Desk: 100\n{!3 - Vault: {!5 2}00}\n";
+
+    let guest = parse_markdown(doc, 1);
+    assert!(guest.contains("<p>Call the front desk.</p>"));
+    assert!(guest.contains("<li>Desk: 100</li>"));
+    assert!(!guest.contains("Vault"));
+    assert!(!guest.contains("<li></li>"));
+
+    let staff = parse_markdown(doc, 3);
+    assert!(staff.contains("<p>Call 555-0100 or the front desk.</p>"));
+    assert!(staff.contains("<li>Vault: 00</li>"));
+
+    let root = parse_markdown(doc, 0);
+    assert!(root.contains("<li>Vault: 200</li>"));
+}
+
+#[test]
+fn search_index_keeps_inline_gated_text_apart() {
+    let sections = wiki::docs::search::index_sections(
+        "Public {!3 secret} words\n!2 {\nStaff {!4 admin}\n!}\n",
+    );
+
+    let levels: Vec<(i32, &str)> = sections
+        .iter()
+        .map(|(required, text)| (required.level, text.as_str()))
+        .collect();
+    assert_eq!(
+        levels,
+        vec![
+            (3, "secret\n"),
+            (0, "Public  words\n"),
+            (4, "admin\n"),
+            (2, "Staff \n"),
+        ]
+    );
+}
+
+#[test]
+fn render_markdown_gates_on_roles() {
+    use wiki::docs::{RenderContext, render_markdown};
+
+    let doc = "Menu\n!@recipes {\nDraft recipes\n!3 {\nSupplier prices\n!}\n!}\nFooter {!@finance budget}\n";
+    let context = RenderContext::default();
+    let roles = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let editor = render_markdown(doc, 1, &roles(&["recipes"]), &context);
+    assert!(editor.contains("Draft recipes"));
+    assert!(!editor.contains("Supplier prices"));
+    assert!(!editor.contains("budget"));
+
+    // A high level alone does not grant a role.
+    let senior = render_markdown(doc, 5, &roles(&["finance"]), &context);
+    assert!(!senior.contains("Draft recipes"));
+    assert!(!senior.contains("Supplier prices"));
+    assert!(senior.contains("Footer budget"));
+
+    let senior_editor = render_markdown(doc, 3, &roles(&["recipes"]), &context);
+    assert!(senior_editor.contains("Supplier prices"));
+
+    let root = render_markdown(doc, 0, &[], &context);
+    assert!(root.contains("Supplier prices"));
+    assert!(root.contains("Footer budget"));
+}
+
 #[tokio::test]
 async fn serve_docs_redirects_to_login_without_jwt() {
     let mut service = wiki::docs::ServeDocs::new("docs/");
@@ -43,11 +154,1873 @@ async fn serve_docs_redirects_to_login_without_jwt() {
         "helper should be present in bootstrap script"
     );
     assert!(
-        body.contains("Bearer"),
-        "script should fetch with bearer token"
+        body.contains("/api/refresh"),
+        "script should refresh the session cookie"
+    );
+    assert!(
+        !body.contains("localStorage"),
+        "script should rely on the session cookies"
     );
     assert!(
         body.contains("encodeURIComponent"),
         "script should encode redirect target"
     );
 }
+
+fn bearer(privileges: i32) -> String {
+    bearer_with_roles(privileges, &[])
+}
+
+fn bearer_with_roles(privileges: i32, roles: &[&str]) -> String {
+    use wiki::user::get_current_timestamp;
+
+    #[derive(serde::Serialize)]
+    struct Claims {
+        sub: String,
+        privileges: i32,
+        roles: Vec<String>,
+        exp: u64,
+    }
+
+    let claims = Claims {
+        sub: "editor".into(),
+        privileges,
+        roles: roles.iter().map(|role| role.to_string()).collect(),
+        exp: get_current_timestamp() + 3600,
+    };
+    let token = wiki::KEYS.encode(&claims).expect("failed to encode token");
+    format!("Bearer {}", token)
+}
+
+/// A docs root and revision database inside a fresh temp dir.
+fn docs_service() -> (tempfile::TempDir, String, wiki::docs::ServeDocs) {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let root = dir.path().join("docs").to_str().unwrap().to_string();
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    let service = wiki::docs::ServeDocs::new(&root).with_database(database);
+    (dir, root, service)
+}
+
+async fn send(
+    service: &mut wiki::docs::ServeDocs,
+    method: &str,
+    uri: &str,
+    privileges: i32,
+    form: Option<&str>,
+) -> axum::response::Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", bearer(privileges))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(form.map(|f| Body::from(f.to_string())).unwrap_or_default())
+        .unwrap();
+    service.call(request).await.unwrap()
+}
+
+async fn body_text(response: axum::response::Response) -> String {
+    let body_bytes = to_bytes(response.into_body(), 1 << 20).await.unwrap();
+    String::from_utf8(body_bytes.to_vec()).unwrap()
+}
+
+/// Appends to an edit form the `base` of `page` as it is on disk now, as the editor would.
+fn with_base(root: &str, page: &str, form: &str) -> String {
+    let current = std::fs::read_to_string(format!("{}/{}.md", root, page)).unwrap_or_default();
+    format!("{}&base={}", form, wiki::docs::edit::content_hash(&current))
+}
+
+#[tokio::test]
+async fn serve_docs_saves_form_edits_and_redirects() {
+    let (_dir, root, mut service) = docs_service();
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/guides/new-page?edit")
+        .header("Authorization", bearer(2))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(with_base(
+            &root,
+            "guides/new-page",
+            "content=%23+Hello%0D%0A%0D%0ABody+text",
+        )))
+        .unwrap();
+
+    let response = service.call(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        response.headers().get("location").unwrap(),
+        "/docs/guides/new-page"
+    );
+
+    let saved = std::fs::read_to_string(format!("{}/guides/new-page.md", root)).unwrap();
+    assert_eq!(saved, "# Hello\n\nBody text");
+}
+
+#[tokio::test]
+async fn serve_docs_accepts_json_edits() {
+    let (_dir, root, mut service) = docs_service();
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/notes?edit")
+        .header("Authorization", bearer(0))
+        .header("content-type", "application/json")
+        .body(Body::from(format!(
+            r#"{{"content":"!2\nsecret notes\n","base":"{}"}}"#,
+            wiki::docs::edit::content_hash("")
+        )))
+        .unwrap();
+
+    let response = service.call(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body_bytes = to_bytes(response.into_body(), 1 << 20).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["path"], "/docs/notes");
+    assert!(body["revision"].as_i64().is_some());
+
+    let saved = std::fs::read_to_string(format!("{}/notes.md", root)).unwrap();
+    assert_eq!(saved, "!2\nsecret notes\n");
+}
+
+#[tokio::test]
+async fn serve_docs_requires_a_base_version_to_save() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(format!("{}/notes.md", root), "Original").unwrap();
+
+    for page in ["/notes?edit", "/brand-new?edit"] {
+        let response = send(&mut service, "POST", page, 2, Some("content=Overwritten")).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::PRECONDITION_REQUIRED,
+            "{}",
+            page
+        );
+    }
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes.md", root)).unwrap(),
+        "Original"
+    );
+    assert!(!std::path::Path::new(&format!("{}/brand-new.md", root)).exists());
+}
+
+#[tokio::test]
+async fn serve_docs_rejects_edits_below_write_level() {
+    let (_dir, root, service) = docs_service();
+    let mut service = service.with_write_level(3);
+
+    for (auth, expected) in [
+        (None, StatusCode::UNAUTHORIZED),
+        (Some(bearer(2)), StatusCode::FORBIDDEN),
+    ] {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/locked?edit")
+            .header("content-type", "application/x-www-form-urlencoded");
+        if let Some(auth) = auth {
+            request = request.header("Authorization", auth);
+        }
+        let request = request.body(Body::from("content=overwritten")).unwrap();
+
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), expected);
+    }
+
+    assert!(!std::path::Path::new(&format!("{}/locked.md", root)).exists());
+}
+
+#[tokio::test]
+async fn serve_docs_refuses_to_save_outside_root() {
+    let (dir, _root, mut service) = docs_service();
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/../escaped?edit")
+        .header("Authorization", bearer(0))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from("content=pwned"))
+        .unwrap();
+
+    let response = service.call(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(!dir.path().join("escaped.md").exists());
+}
+
+#[test]
+fn diff_lines_marks_added_and_removed_lines() {
+    use wiki::docs::diff::{DiffLine, diff_lines};
+
+    let lines = diff_lines("a\nb\nc\n", "a\nc\nd\n");
+
+    assert_eq!(
+        lines,
+        vec![
+            DiffLine::Same("a"),
+            DiffLine::Removed("b"),
+            DiffLine::Same("c"),
+            DiffLine::Added("d"),
+        ]
+    );
+}
+
+#[test]
+fn diff_lines_handles_large_revisions() {
+    use wiki::docs::diff::{DiffLine, diff_lines, merge3};
+
+    // A quadratic table for these would need tens of gigabytes.
+    let old: String = (0..50_000).map(|n| format!("line {}\n", n)).collect();
+    let new = old.replace("line 25000\n", "changed\n");
+
+    let lines = diff_lines(&old, &new);
+    assert_eq!(lines.len(), 50_001);
+    assert!(lines.contains(&DiffLine::Removed("line 25000")));
+    assert!(lines.contains(&DiffLine::Added("changed")));
+
+    let unrelated: String = (0..20_000).map(|n| format!("other {}\n", n)).collect();
+    let lines = diff_lines(&old, &unrelated);
+    assert_eq!(lines.len(), 70_000);
+
+    let merge = merge3(&old, &new, &old.replace("line 10\n", "theirs\n"));
+    assert_eq!(merge.conflicts, 0);
+    assert!(merge.text.contains("changed\n") && merge.text.contains("theirs\n"));
+}
+
+#[tokio::test]
+async fn serve_docs_records_history_diff_and_revert() {
+    let (_dir, root, mut service) = docs_service();
+
+    let first = send(
+        &mut service,
+        "POST",
+        "/runbook?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "runbook",
+            "content=step+one%0Astep+two&summary=first+draft",
+        )),
+    )
+    .await;
+    assert_eq!(first.status(), StatusCode::SEE_OTHER);
+    let second = send(
+        &mut service,
+        "POST",
+        "/runbook?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "runbook",
+            "content=step+one%0Astep+three&summary=%3Cb%3Efix%3C%2Fb%3E",
+        )),
+    )
+    .await;
+    assert_eq!(second.status(), StatusCode::SEE_OTHER);
+
+    let history = body_text(send(&mut service, "GET", "/runbook?history", 2, None).await).await;
+    assert!(history.contains("first draft"));
+    assert!(history.contains("&lt;b&gt;fix&lt;/b&gt;"));
+    assert!(history.contains("editor"));
+    assert!(history.contains("?diff=1..2"));
+
+    let diff = body_text(send(&mut service, "GET", "/runbook?diff=1..2", 2, None).await).await;
+    assert!(diff.contains("<span class=\"diff-removed\">- step two</span>"));
+    assert!(diff.contains("<span class=\"diff-added\">+ step three</span>"));
+
+    let revert = send(&mut service, "POST", "/runbook?revert=1", 2, None).await;
+    assert_eq!(revert.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/runbook.md", root)).unwrap(),
+        "step one\nstep two"
+    );
+
+    let history = body_text(send(&mut service, "GET", "/runbook?history", 2, None).await).await;
+    assert!(history.contains("Reverted to revision #1"));
+}
+
+#[tokio::test]
+async fn serve_docs_history_requires_write_level_and_matching_page() {
+    let (_dir, root, mut service) = docs_service();
+
+    send(
+        &mut service,
+        "POST",
+        "/a?edit",
+        2,
+        Some(&with_base(&root, "a", "content=alpha")),
+    )
+    .await;
+    send(
+        &mut service,
+        "POST",
+        "/b?edit",
+        2,
+        Some(&with_base(&root, "b", "content=beta")),
+    )
+    .await;
+
+    let forbidden = send(&mut service, "GET", "/a?history", 1, None).await;
+    assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+
+    let foreign = send(&mut service, "GET", "/a?diff=1..2", 2, None).await;
+    assert_eq!(foreign.status(), StatusCode::NOT_FOUND);
+
+    let foreign_revert = send(&mut service, "POST", "/a?revert=2", 2, None).await;
+    assert_eq!(foreign_revert.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn serve_docs_history_views_escape_the_page_path() {
+    let (_dir, root, mut service) = docs_service();
+    let uri = "/x\"onmouseover=\"alert(1)";
+
+    for content in ["content=one", "content=two"] {
+        send(
+            &mut service,
+            "POST",
+            &format!("{}?edit", uri),
+            2,
+            Some(&with_base(&root, &uri[1..], content)),
+        )
+        .await;
+    }
+
+    for query in ["history", "diff=1..2", "revert=1"] {
+        let response = send(&mut service, "GET", &format!("{}?{}", uri, query), 2, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", query);
+        let html = body_text(response).await;
+        assert!(!html.contains("x\"onmouseover"), "{}: {}", query, html);
+        assert!(html.contains("x&quot;onmouseover"), "{}", query);
+    }
+}
+
+#[test]
+fn merge3_combines_independent_edits_and_marks_overlaps() {
+    use wiki::docs::diff::merge3;
+
+    let base = "title\nintro\nbody\nfooter\n";
+
+    let clean = merge3(
+        base,
+        "title\nintro v2\nbody\nfooter\n",
+        "title\nintro\nbody\nfooter v2\n",
+    );
+    assert_eq!(clean.conflicts, 0);
+    assert_eq!(clean.text, "title\nintro v2\nbody\nfooter v2\n");
+
+    let conflicting = merge3(
+        base,
+        "title\nmine\nbody\nfooter\n",
+        "title\ntheirs\nbody\nfooter\n",
+    );
+    assert_eq!(conflicting.conflicts, 1);
+    assert_eq!(
+        conflicting.text,
+        "title\n<<<<<<< yours\nmine\n=======\ntheirs\n>>>>>>> theirs\nbody\nfooter\n"
+    );
+}
+
+#[tokio::test]
+async fn serve_docs_rejects_stale_edits_with_merge_view() {
+    use wiki::docs::edit::content_hash;
+
+    let (_dir, root, mut service) = docs_service();
+    let base = "one\ntwo\nthree\n";
+    send(
+        &mut service,
+        "POST",
+        "/shared?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "shared",
+            &format!("content={}", urlencoding::encode(base)),
+        )),
+    )
+    .await;
+
+    let form = body_text(send(&mut service, "GET", "/shared?edit", 2, None).await).await;
+    let base_hash = content_hash(base);
+    assert!(form.contains(&format!("name=\"base\" value=\"{}\"", base_hash)));
+
+    // Another editor saves first, starting from the same base.
+    let theirs = send(
+        &mut service,
+        "POST",
+        "/shared?edit",
+        2,
+        Some(&format!(
+            "content={}&base={}",
+            urlencoding::encode("one\ntwo\nTHREE\n"),
+            base_hash
+        )),
+    )
+    .await;
+    assert_eq!(theirs.status(), StatusCode::SEE_OTHER);
+
+    let stale = send(
+        &mut service,
+        "POST",
+        "/shared?edit",
+        2,
+        Some(&format!(
+            "content={}&base={}",
+            urlencoding::encode("ONE\ntwo\nthree\n"),
+            base_hash
+        )),
+    )
+    .await;
+    assert_eq!(stale.status(), StatusCode::CONFLICT);
+    let body = body_text(stale).await;
+    assert!(body.contains("Their changes"));
+    assert!(body.contains("ONE\ntwo\nTHREE\n"));
+    assert!(body.contains(&content_hash("one\ntwo\nTHREE\n")));
+
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/shared.md", root)).unwrap(),
+        "one\ntwo\nTHREE\n"
+    );
+}
+
+#[tokio::test]
+async fn serve_docs_reports_json_conflicts() {
+    let (_dir, root, mut service) = docs_service();
+    send(
+        &mut service,
+        "POST",
+        "/api-page?edit",
+        2,
+        Some(&with_base(&root, "api-page", "content=current")),
+    )
+    .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api-page?edit")
+        .header("Authorization", bearer(2))
+        .header("content-type", "application/json")
+        .body(Body::from(
+            r#"{"content":"mine","base":"not-the-current-hash"}"#,
+        ))
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+    assert_eq!(body["error"], "conflict");
+    assert_eq!(body["current"], "current");
+    assert_eq!(body["base"], wiki::docs::edit::content_hash("current"));
+    // The base is not a recorded revision, so neither side's edits may be dropped.
+    assert_eq!(body["conflicts"], 1);
+    assert_eq!(
+        body["merged"],
+        "<<<<<<< yours\nmine\n=======\ncurrent\n>>>>>>> theirs\n"
+    );
+}
+
+#[tokio::test]
+async fn serve_docs_merges_stale_edits_of_pages_changed_outside_the_wiki() {
+    use wiki::docs::edit::content_hash;
+
+    let (dir, root, mut service) = docs_service();
+    let base = "one\ntwo\nthree\n";
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(format!("{}/manual.md", root), base).unwrap();
+
+    // Opening the editor leaves the history alone.
+    let form = body_text(send(&mut service, "GET", "/manual?edit", 2, None).await).await;
+    let base_hash = content_hash(base);
+    assert!(form.contains(&format!("name=\"base\" value=\"{}\"", base_hash)));
+
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    assert!(database.revisions("manual").await.unwrap().is_empty());
+
+    let theirs = send(
+        &mut service,
+        "POST",
+        "/manual?edit",
+        2,
+        Some(&format!(
+            "content={}&base={}",
+            urlencoding::encode("one\ntwo\nTHREE\n"),
+            base_hash
+        )),
+    )
+    .await;
+    assert_eq!(theirs.status(), StatusCode::SEE_OTHER);
+
+    // The save recorded the file as it was on disk before replacing it.
+    let revisions = database.revisions("manual").await.unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[1].content, base);
+    assert_eq!(revisions[1].author, "(outside the wiki)");
+
+    let stale = send(
+        &mut service,
+        "POST",
+        "/manual?edit",
+        2,
+        Some(&format!(
+            "content={}&base={}",
+            urlencoding::encode("ONE\ntwo\nthree\n"),
+            base_hash
+        )),
+    )
+    .await;
+    assert_eq!(stale.status(), StatusCode::CONFLICT);
+    let body = body_text(stale).await;
+    assert!(body.contains("Their changes"));
+    assert!(body.contains("without conflicts"));
+    assert!(body.contains("ONE\ntwo\nTHREE\n"));
+}
+
+#[tokio::test]
+async fn serve_docs_search_highlights_readable_matches() {
+    let (dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/ops", root)).unwrap();
+    std::fs::write(
+        format!("{}/ops/oncall.md", root),
+        "Page the <oncall> engineer\n!3\nThe escalation phone is 555-0100\n",
+    )
+    .unwrap();
+    std::fs::write(format!("{}/.ops.md.1.tmp", root), "engineer leftovers\n").unwrap();
+
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    let indexed = wiki::docs::reindex(&database, &root, &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(indexed, 1);
+
+    let results = body_text(send(&mut service, "GET", "/?search=engineer", 1, None).await).await;
+    assert!(results.contains("<a href=\"/docs/ops/oncall\">ops/oncall</a>"));
+    assert!(results.contains("&lt;oncall&gt; <mark>engineer</mark>"));
+
+    let hidden = body_text(send(&mut service, "GET", "/?search=escalation", 1, None).await).await;
+    assert!(hidden.contains("No pages matched"));
+
+    let visible = body_text(send(&mut service, "GET", "/?search=escalation", 3, None).await).await;
+    assert!(visible.contains("ops/oncall"));
+
+    // The search form submits spaces as `+`.
+    let words =
+        body_text(send(&mut service, "GET", "/?search=escalation+phone", 3, None).await).await;
+    assert!(words.contains("ops/oncall"));
+    assert!(words.contains("<mark>escalation</mark> <mark>phone</mark>"));
+}
+
+#[tokio::test]
+async fn serve_docs_reindexes_pages_on_save() {
+    let (_dir, root, mut service) = docs_service();
+
+    send(
+        &mut service,
+        "POST",
+        "/fresh?edit",
+        2,
+        Some(&with_base(&root, "fresh", "content=brand+new+wording")),
+    )
+    .await;
+    let found = body_text(send(&mut service, "GET", "/?search=wording", 2, None).await).await;
+    assert!(found.contains("/docs/fresh"));
+
+    send(
+        &mut service,
+        "POST",
+        "/fresh?edit",
+        2,
+        Some(&with_base(&root, "fresh", "content=rewritten")),
+    )
+    .await;
+    let stale = body_text(send(&mut service, "GET", "/?search=wording", 2, None).await).await;
+    assert!(stale.contains("No pages matched"));
+}
+
+#[tokio::test]
+async fn render_markdown_resolves_wiki_links_against_docs_root() {
+    use wiki::docs::{RenderContext, links, render_markdown};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().to_str().unwrap();
+    std::fs::create_dir_all(dir.path().join("plans")).unwrap();
+    std::fs::write(dir.path().join("apples.md"), "# Apples\n").unwrap();
+    std::fs::write(dir.path().join("plans/serve_docs.md"), "# Plan\n").unwrap();
+
+    let doc = "See [[Apples]], [[plans/Serve Docs|the plan]] and [[Brand New Page]].\n\n`[[not a link]]`\n";
+    let context = RenderContext {
+        links: Some(links::load(root, &[doc], &Default::default(), None).await),
+        ..RenderContext::default()
+    };
+    let rendered = render_markdown(doc, 1, &[], &context);
+
+    assert!(rendered.contains("<a href=\"/docs/apples\" data-wikilink=\"true\">Apples</a>"));
+    assert!(
+        rendered.contains("<a href=\"/docs/plans/serve_docs\" data-wikilink=\"true\">the plan</a>")
+    );
+    assert!(rendered.contains(
+        "<a href=\"/docs/brand-new-page?edit\" data-wikilink=\"true\">Brand New Page</a>"
+    ));
+    assert!(rendered.contains("<code>[[not a link]]</code>"));
+}
+
+#[tokio::test]
+async fn serve_docs_renders_unreadable_wiki_link_targets_as_missing() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/hr", root)).unwrap();
+    std::fs::write(format!("{}/hr/salaries.md", root), "# Salaries\n").unwrap();
+    std::fs::write(format!("{}/hr/.access.toml", root), "read = 3\n").unwrap();
+    std::fs::write(
+        format!("{}/index.md", root),
+        "See [[hr/salaries]] and [[hr/nothing]].\n",
+    )
+    .unwrap();
+
+    let guest = body_text(send(&mut service, "GET", "/index", 1, None).await).await;
+    assert!(guest.contains("href=\"/docs/hr/salaries?edit\""));
+    assert!(guest.contains("href=\"/docs/hr/nothing?edit\""));
+
+    let staff = body_text(send(&mut service, "GET", "/index", 3, None).await).await;
+    assert!(staff.contains("href=\"/docs/hr/salaries\""));
+    assert!(staff.contains("href=\"/docs/hr/nothing?edit\""));
+}
+
+#[tokio::test]
+async fn serve_docs_shows_readable_backlinks() {
+    let (_dir, root, mut service) = docs_service();
+
+    send(
+        &mut service,
+        "POST",
+        "/faq?edit",
+        2,
+        Some(&with_base(&root, "faq", "content=Answers")),
+    )
+    .await;
+    send(
+        &mut service,
+        "POST",
+        "/intro?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "intro",
+            "content=Read+the+%5B%5BFAQ%5D%5D",
+        )),
+    )
+    .await;
+    send(
+        &mut service,
+        "POST",
+        "/ops/secrets?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "ops/secrets",
+            "content=%213%0ASee+%5Bthe+faq%5D%28%2Fdocs%2Ffaq%23top%29",
+        )),
+    )
+    .await;
+
+    let guest_view = body_text(send(&mut service, "GET", "/faq", 1, None).await).await;
+    assert!(guest_view.contains("Linked from"));
+    assert!(guest_view.contains("<a href=\"/docs/intro\">intro</a>"));
+    assert!(!guest_view.contains("<a href=\"/docs/ops/secrets\">ops/secrets</a>"));
+
+    let privileged_view = body_text(send(&mut service, "GET", "/faq", 3, None).await).await;
+    assert!(privileged_view.contains("<a href=\"/docs/ops/secrets\">ops/secrets</a>"));
+
+    let unlinked = body_text(send(&mut service, "GET", "/intro", 3, None).await).await;
+    assert!(!unlinked.contains("Linked from"));
+}
+
+#[tokio::test]
+async fn serve_docs_percent_encodes_page_links() {
+    let (dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(format!("{}/faq.md", root), "Answers\n").unwrap();
+    std::fs::write(
+        format!("{}/release notes.md", root),
+        "---\ntags: [news]\n---\nSee the [[faq]]\n",
+    )
+    .unwrap();
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    wiki::docs::reindex(&database, &root, &Default::default())
+        .await
+        .unwrap();
+
+    let link = "<a href=\"/docs/release%20notes\">release notes</a>";
+    for uri in ["/faq", "/?search=faq", "/?tag=news"] {
+        let html = body_text(send(&mut service, "GET", uri, 1, None).await).await;
+        assert!(html.contains(link), "{}: {}", uri, html);
+    }
+}
+
+#[tokio::test]
+async fn serve_docs_reads_roles_from_the_token() {
+    let (dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        format!("{}/kitchen.md", root),
+        "Opening hours\n!@recipes\nSecret sauce\n",
+    )
+    .unwrap();
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    wiki::docs::reindex(&database, &root, &Default::default())
+        .await
+        .unwrap();
+
+    let get = |authorization: String, uri: &str| {
+        Request::builder()
+            .uri(uri)
+            .header("Authorization", authorization)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let guest = body_text(service.call(get(bearer(4), "/kitchen")).await.unwrap()).await;
+    assert!(!guest.contains("Secret sauce"));
+
+    let cook = get(bearer_with_roles(1, &["recipes"]), "/kitchen");
+    let cook = body_text(service.call(cook).await.unwrap()).await;
+    assert!(cook.contains("Secret sauce"));
+
+    let found = get(bearer_with_roles(1, &["recipes"]), "/?search=sauce");
+    let found = body_text(service.call(found).await.unwrap()).await;
+    assert!(found.contains("/docs/kitchen"));
+    let hidden = body_text(
+        service
+            .call(get(bearer(4), "/?search=sauce"))
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert!(hidden.contains("No pages matched"));
+
+    // The root role reads everything, like privilege level 0.
+    let admin = get(bearer_with_roles(1, &["root"]), "/kitchen");
+    let admin = body_text(service.call(admin).await.unwrap()).await;
+    assert!(admin.contains("Secret sauce"));
+}
+
+#[tokio::test]
+async fn serve_docs_enforces_access_policies_before_reading_pages() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/finance/reports", root)).unwrap();
+    std::fs::write(format!("{}/finance/.access.toml", root), "read = 3\n").unwrap();
+    std::fs::write(
+        format!("{}/finance/reports/.access.toml", root),
+        "read = 1\nwrite = \"@finance\"\n\n[pages.payroll]\nread = 5\n",
+    )
+    .unwrap();
+    std::fs::write(format!("{}/finance/reports/q3.md", root), "Revenue\n").unwrap();
+    std::fs::write(format!("{}/finance/reports/payroll.md", root), "Salaries\n").unwrap();
+
+    // Missing pages are refused too, so their existence is not revealed.
+    let missing = send(&mut service, "GET", "/finance/nothing-here", 1, None).await;
+    assert_eq!(missing.status(), StatusCode::FORBIDDEN);
+
+    // A subdirectory cannot loosen its parent.
+    let guest = send(&mut service, "GET", "/finance/reports/q3", 1, None).await;
+    assert_eq!(guest.status(), StatusCode::FORBIDDEN);
+    let analyst = send(&mut service, "GET", "/finance/reports/q3", 3, None).await;
+    assert_eq!(analyst.status(), StatusCode::OK);
+
+    let payroll = send(&mut service, "GET", "/finance/reports/payroll", 3, None).await;
+    assert_eq!(payroll.status(), StatusCode::FORBIDDEN);
+    let payroll = send(&mut service, "GET", "/finance/reports/payroll", 5, None).await;
+    assert_eq!(payroll.status(), StatusCode::OK);
+
+    // Writing needs the `finance` role on top of the server-wide write level.
+    let edit = send(&mut service, "GET", "/finance/reports/q3?edit", 3, None).await;
+    assert_eq!(edit.status(), StatusCode::FORBIDDEN);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/finance/reports/q3?edit")
+        .header("Authorization", bearer_with_roles(3, &["finance"]))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(with_base(
+            &root,
+            "finance/reports/q3",
+            "content=Revenue+up",
+        )))
+        .unwrap();
+    let saved = service.call(request).await.unwrap();
+    assert_eq!(saved.status(), StatusCode::SEE_OTHER);
+
+    let root_view = send(
+        &mut service,
+        "GET",
+        "/finance/reports/payroll?history",
+        0,
+        None,
+    )
+    .await;
+    assert_eq!(root_view.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn serve_docs_hides_policy_protected_pages_from_search_and_backlinks() {
+    let (dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/vault", root)).unwrap();
+    std::fs::write(format!("{}/vault/.access.toml", root), "read = 4\n").unwrap();
+    std::fs::write(format!("{}/vault/keys.md", root), "Treasure [[FAQ]]\n").unwrap();
+    std::fs::write(format!("{}/faq.md", root), "Treasure hunting FAQ\n").unwrap();
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    wiki::docs::reindex(&database, &root, &Default::default())
+        .await
+        .unwrap();
+
+    let guest = body_text(send(&mut service, "GET", "/?search=treasure", 1, None).await).await;
+    assert!(guest.contains("/docs/faq"));
+    assert!(!guest.contains("vault/keys"));
+    let faq = body_text(send(&mut service, "GET", "/faq", 1, None).await).await;
+    assert!(!faq.contains("vault/keys"));
+
+    let keeper = body_text(send(&mut service, "GET", "/?search=treasure", 4, None).await).await;
+    assert!(keeper.contains("/docs/vault/keys"));
+    let faq = body_text(send(&mut service, "GET", "/faq", 4, None).await).await;
+    assert!(faq.contains("vault/keys"));
+}
+
+#[tokio::test]
+async fn serve_docs_refuses_pages_under_a_malformed_policy() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/team", root)).unwrap();
+    std::fs::write(format!("{}/team/.access.toml", root), "raed = 2\n").unwrap();
+    std::fs::write(format!("{}/team/roster.md", root), "Everyone\n").unwrap();
+
+    let response = send(&mut service, "GET", "/team/roster", 0, None).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn front_matter_is_parsed_and_never_rendered() {
+    use wiki::docs::front_matter;
+
+    let yaml = "---\ntitle: Apple pie\ndescription: Grandma's recipe\ntags: [baking, dessert]\nserves: 8\n---\n# Apple pie\n";
+    let meta = front_matter::parse(yaml).unwrap();
+    assert_eq!(meta.title.as_deref(), Some("Apple pie"));
+    assert_eq!(meta.description.as_deref(), Some("Grandma's recipe"));
+    assert_eq!(meta.tags, vec!["baking", "dessert"]);
+    assert_eq!(meta.extra["serves"], 8);
+    let rendered = parse_markdown(yaml, 1);
+    assert!(!rendered.contains("Grandma"));
+    assert!(!rendered.contains("<hr>"));
+    assert!(rendered.contains("<h1 id=\"apple-pie\">Apple pie"));
+
+    let toml = "+++\ntitle = \"Tart\"\nauthor = \"Sam\"\n+++\nBody\n";
+    let meta = front_matter::parse(toml).unwrap();
+    assert_eq!(meta.title.as_deref(), Some("Tart"));
+    assert_eq!(meta.author.as_deref(), Some("Sam"));
+    assert_eq!(parse_markdown(toml, 1), "<p>Body</p>\n");
+
+    // A thematic break further down is not front matter.
+    let plain = "Intro\n\n---\n\nMore\n";
+    assert_eq!(front_matter::parse(plain).unwrap(), Default::default());
+    assert!(parse_markdown(plain, 1).contains("<hr>"));
+
+    assert!(front_matter::parse("---\ntitle: [unclosed\n---\n").is_err());
+}
+
+#[tokio::test]
+async fn serve_docs_puts_front_matter_in_the_page_head() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        format!("{}/pie.md", root),
+        "---\ntitle: Pie <3\ndescription: \"Flaky \\\"crust\\\"\"\n---\nBake it.\n",
+    )
+    .unwrap();
+    std::fs::write(format!("{}/plain.md", root), "No metadata\n").unwrap();
+
+    let html = body_text(send(&mut service, "GET", "/pie", 1, None).await).await;
+    assert!(html.contains("<title>Pie &lt;3</title>"));
+    assert!(html.contains("<meta name=\"description\" content=\"Flaky &quot;crust&quot;\">"));
+    assert!(html.contains("<p>Bake it.</p>"));
+    assert!(!html.contains("title: Pie"));
+
+    let html = body_text(send(&mut service, "GET", "/plain", 1, None).await).await;
+    assert!(html.contains("<title>plain</title>"));
+    assert!(!html.contains("name=\"description\""));
+}
+
+#[tokio::test]
+async fn serve_docs_lists_readable_pages_by_tag() {
+    let (dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/vault", root)).unwrap();
+    std::fs::write(format!("{}/vault/.access.toml", root), "read = 4\n").unwrap();
+    std::fs::write(
+        format!("{}/vault/recipe.md", root),
+        "---\ntags: [Baking]\n---\nSecret\n",
+    )
+    .unwrap();
+    std::fs::write(
+        format!("{}/pie.md", root),
+        "---\ntags: [baking, \" dessert \", baking]\n---\nPie\n",
+    )
+    .unwrap();
+    std::fs::write(
+        format!("{}/plans.md", root),
+        "---\ntags: [baking]\n---\n!3\nNext season's pies\n",
+    )
+    .unwrap();
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    wiki::docs::reindex(&database, &root, &Default::default())
+        .await
+        .unwrap();
+
+    let guest = body_text(send(&mut service, "GET", "/?tag=Baking", 1, None).await).await;
+    assert!(guest.contains("<a href=\"/docs/pie\">pie</a>"));
+    assert!(!guest.contains("vault/recipe"));
+    // Like search, pages gated above the reader throughout are left out.
+    assert!(!guest.contains("/docs/plans"));
+
+    let keeper = body_text(send(&mut service, "GET", "/?tag=baking", 4, None).await).await;
+    assert!(keeper.contains("<a href=\"/docs/vault/recipe\">vault/recipe</a>"));
+    assert!(keeper.contains("<a href=\"/docs/plans\">plans</a>"));
+
+    let none = body_text(send(&mut service, "GET", "/?tag=nothing", 4, None).await).await;
+    assert!(none.contains("No pages have this tag."));
+
+    let page = body_text(send(&mut service, "GET", "/pie", 1, None).await).await;
+    assert!(page.contains(
+        "<ul class=\"tags\"><li><a href=\"/docs?tag=baking\">baking</a></li><li><a href=\"/docs?tag=dessert\">dessert</a></li></ul>"
+    ));
+
+    // Saving a page retags it.
+    send(
+        &mut service,
+        "POST",
+        "/pie?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "pie",
+            "content=---%0Atags%3A+%5Bsavoury%5D%0A---%0APie",
+        )),
+    )
+    .await;
+    let retagged = body_text(send(&mut service, "GET", "/?tag=baking", 1, None).await).await;
+    assert!(!retagged.contains("/docs/pie"));
+}
+
+#[tokio::test]
+async fn serve_docs_generates_directory_indexes() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/plans/q3", root)).unwrap();
+    std::fs::write(format!("{}/welcome.md", root), "Hello\n").unwrap();
+    std::fs::write(format!("{}/plans/roadmap.md", root), "Roadmap\n").unwrap();
+    std::fs::write(format!("{}/plans/q3/budget.md", root), "Budget\n").unwrap();
+
+    let top = send(&mut service, "GET", "/", 1, None).await;
+    assert_eq!(top.status(), StatusCode::OK);
+    let top = body_text(top).await;
+    assert!(top.contains(
+        "<ul class=\"directory-index\"><li><a href=\"/docs/plans/\">plans/</a></li><li><a href=\"/docs/welcome\">welcome</a></li></ul>"
+    ));
+
+    for uri in ["/plans", "/plans/"] {
+        let plans = body_text(send(&mut service, "GET", uri, 1, None).await).await;
+        assert!(plans.contains("<h1>plans</h1>"));
+        assert!(plans.contains("<a href=\"/docs/plans/q3/\">q3/</a>"));
+        assert!(plans.contains("<a href=\"/docs/plans/roadmap\">roadmap</a>"));
+        let index = &plans[plans.find("<ul class=\"directory-index\">").unwrap()..];
+        assert!(!index[..index.find("</ul>").unwrap()].contains("welcome"));
+    }
+
+    let missing = send(&mut service, "GET", "/plans/q4/", 1, None).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    let climbing = send(&mut service, "GET", "/plans/..", 1, None).await;
+    assert_eq!(climbing.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn serve_docs_prefers_an_index_page_for_directories() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/plans", root)).unwrap();
+    std::fs::write(
+        format!("{}/plans/index.md", root),
+        "---\ntitle: All plans\n---\n# Our plans\n",
+    )
+    .unwrap();
+    std::fs::write(format!("{}/plans/roadmap.md", root), "Roadmap\n").unwrap();
+
+    let plans = body_text(send(&mut service, "GET", "/plans/", 1, None).await).await;
+    assert!(plans.contains("<title>All plans</title>"));
+    assert!(plans.contains("Our plans"));
+    assert!(!plans.contains("class=\"directory-index\""));
+    // The directory link stands in for the index page in the sidebar.
+    assert!(plans.contains("<a href=\"/docs/plans/\" aria-current=\"page\">plans</a>"));
+    assert!(!plans.contains("/docs/plans/index"));
+}
+
+#[tokio::test]
+async fn serve_docs_sidebar_lists_only_readable_pages() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/vault", root)).unwrap();
+    std::fs::create_dir_all(format!("{}/guides", root)).unwrap();
+    std::fs::write(format!("{}/vault/.access.toml", root), "read = 4\n").unwrap();
+    std::fs::write(format!("{}/vault/keys.md", root), "Keys\n").unwrap();
+    std::fs::write(format!("{}/guides/setup.md", root), "Setup\n").unwrap();
+    std::fs::write(
+        format!("{}/guides/.access.toml", root),
+        "[pages.secret]\nread = 4\n",
+    )
+    .unwrap();
+    std::fs::write(format!("{}/guides/secret.md", root), "Secret\n").unwrap();
+
+    let guest = body_text(send(&mut service, "GET", "/guides/setup", 1, None).await).await;
+    assert!(guest.contains(
+        "<nav class=\"docs-nav\" aria-label=\"Docs\"><a href=\"/docs/\">Docs</a><ul><li><a href=\"/docs/guides/\">guides</a><ul><li><a href=\"/docs/guides/setup\" aria-current=\"page\">setup</a></li></ul></li></ul></nav>"
+    ));
+
+    let index = body_text(send(&mut service, "GET", "/", 1, None).await).await;
+    assert!(!index.contains("vault"));
+    let vault = send(&mut service, "GET", "/vault/", 1, None).await;
+    assert_eq!(vault.status(), StatusCode::FORBIDDEN);
+
+    let keeper = body_text(send(&mut service, "GET", "/vault/", 4, None).await).await;
+    assert!(keeper.contains("<a href=\"/docs/vault/keys\">keys</a>"));
+    assert!(keeper.contains("<a href=\"/docs/guides/secret\">secret</a>"));
+}
+
+#[test]
+fn headings_get_slug_ids_and_permalinks() {
+    let rendered = parse_markdown("# Getting started\n\n## Step *one*\n\n## Step one\n", 1);
+    assert!(rendered.contains(
+        "<h1 id=\"getting-started\">Getting started<a class=\"permalink\" href=\"#getting-started\" aria-label=\"Permalink\">#</a></h1>"
+    ));
+    assert!(rendered.contains("<h2 id=\"step-one\">Step <em>one</em>"));
+    assert!(rendered.contains("<h2 id=\"step-one-1\">"));
+}
+
+#[test]
+fn toc_marker_lists_only_visible_headings() {
+    let doc = "# Runbook\n\n[[toc]]\n\n## Restart\n\n### Check logs\n\n!3\n## Rotate keys\n!end\n## Escalate\n";
+
+    let guest = parse_markdown(doc, 1);
+    assert!(guest.contains(
+        "<nav class=\"toc\" aria-label=\"Contents\"><ul><li><a href=\"#runbook\">Runbook</a><ul><li><a href=\"#restart\">Restart</a><ul><li><a href=\"#check-logs\">Check logs</a></li></ul></li><li><a href=\"#escalate\">Escalate</a></li></ul></li></ul></nav>"
+    ));
+    assert!(!guest.contains("Rotate keys"));
+    assert!(!guest.contains("[[toc]]"));
+    assert!(!guest.contains("/docs/toc"));
+
+    let operator = parse_markdown(doc, 3);
+    assert!(operator.contains("<a href=\"#rotate-keys\">Rotate keys</a>"));
+}
+
+#[test]
+fn toc_is_added_automatically_to_long_pages() {
+    use wiki::docs::{RenderContext, render_markdown};
+
+    let context = RenderContext {
+        auto_toc: Some(3),
+        ..RenderContext::default()
+    };
+    let short = render_markdown("## One\n\n## Two\n", 1, &[], &context);
+    assert!(!short.contains("class=\"toc\""));
+
+    let long = render_markdown("## One\n\n## Two\n\n## Three\n", 1, &[], &context);
+    assert!(long.starts_with("<nav class=\"toc\" aria-label=\"Contents\">"));
+
+    // Gated headings do not count towards the threshold.
+    let gated = render_markdown("## One\n\n## Two\n\n!2\n## Three\n", 1, &[], &context);
+    assert!(!gated.contains("class=\"toc\""));
+
+    // Without a threshold only `[[toc]]` adds one.
+    assert!(!parse_markdown("## One\n\n## Two\n\n## Three\n", 1).contains("class=\"toc\""));
+}
+
+#[test]
+fn fenced_code_is_highlighted_with_theme_classes() {
+    let doc = "```rust\nlet greeting = \"<hi>\";\n```\n";
+    let rendered = parse_markdown(doc, 1);
+    assert!(rendered.contains("<pre class=\"syntax-highlighting\"><code class=\"language-rust\">"));
+    assert!(rendered.contains("<span class=\"hl-storage hl-type hl-rust\">let</span>"));
+    assert!(rendered.contains("&lt;hi&gt;"));
+    assert!(!rendered.contains("style="));
+
+    let css = wiki::docs::highlight::stylesheet();
+    assert!(css.contains("\n.syntax-highlighting {"));
+    assert!(css.contains("html.dark .syntax-highlighting {"));
+    assert!(css.contains("html.dark .hl-comment"));
+}
+
+fn render_with(doc: &str, extensions: wiki::config::MarkdownExtensions) -> String {
+    use wiki::docs::{RenderContext, render_markdown};
+
+    let context = RenderContext {
+        extensions,
+        ..RenderContext::default()
+    };
+    render_markdown(doc, 1, &[], &context)
+}
+
+#[test]
+fn markdown_renders_tables_by_default() {
+    let doc = "| Fruit | Colour |\n| --- | --- |\n| Apple | Red |\n";
+    let rendered = parse_markdown(doc, 1);
+    assert!(rendered.contains("<table>"));
+    assert!(rendered.contains("<td>Apple</td>"));
+
+    let plain = render_with(
+        doc,
+        wiki::config::MarkdownExtensions {
+            table: false,
+            ..Default::default()
+        },
+    );
+    assert!(!plain.contains("<table>"));
+}
+
+#[test]
+fn markdown_renders_strikethrough_by_default() {
+    assert!(parse_markdown("~~gone~~", 1).contains("<del>gone</del>"));
+
+    let plain = render_with(
+        "~~gone~~",
+        wiki::config::MarkdownExtensions {
+            strikethrough: false,
+            ..Default::default()
+        },
+    );
+    assert!(!plain.contains("<del>"));
+}
+
+#[test]
+fn markdown_renders_task_lists_by_default() {
+    let rendered = parse_markdown("- [x] Done\n- [ ] Todo\n", 1);
+    assert!(rendered.contains("<input type=\"checkbox\" checked=\"\" disabled=\"\"> Done"));
+    assert!(rendered.contains("<input type=\"checkbox\" disabled=\"\"> Todo"));
+
+    let plain = render_with(
+        "- [x] Done\n",
+        wiki::config::MarkdownExtensions {
+            tasklist: false,
+            ..Default::default()
+        },
+    );
+    assert!(!plain.contains("checkbox"));
+}
+
+#[test]
+fn markdown_autolinks_bare_urls_by_default() {
+    let rendered = parse_markdown("See https://example.com/docs today", 1);
+    assert!(rendered.contains("<a href=\"https://example.com/docs\">https://example.com/docs</a>"));
+
+    let plain = render_with(
+        "See https://example.com/docs today",
+        wiki::config::MarkdownExtensions {
+            autolink: false,
+            ..Default::default()
+        },
+    );
+    assert!(!plain.contains("<a "));
+}
+
+#[test]
+fn markdown_renders_footnotes_by_default() {
+    let doc = "Apples[^1] are tasty.\n\n[^1]: Mostly.\n";
+    let rendered = parse_markdown(doc, 1);
+    assert!(rendered.contains("<sup class=\"footnote-ref\">"));
+    assert!(rendered.contains("<section class=\"footnotes\" data-footnotes=\"\">"));
+
+    let plain = render_with(
+        doc,
+        wiki::config::MarkdownExtensions {
+            footnotes: false,
+            ..Default::default()
+        },
+    );
+    assert!(!plain.contains("footnote"));
+}
+
+#[test]
+fn markdown_renders_description_lists_when_enabled() {
+    let doc = "Apple\n\n: A red fruit.\n";
+    assert!(!parse_markdown(doc, 1).contains("<dl>"));
+
+    let rendered = render_with(
+        doc,
+        wiki::config::MarkdownExtensions {
+            description_lists: true,
+            ..Default::default()
+        },
+    );
+    assert!(rendered.contains("<dl>"));
+    assert!(rendered.contains("<dt>Apple</dt>"));
+}
+
+#[test]
+fn markdown_renders_superscript_when_enabled() {
+    assert!(!parse_markdown("E = mc^2^", 1).contains("<sup>"));
+
+    let rendered = render_with(
+        "E = mc^2^",
+        wiki::config::MarkdownExtensions {
+            superscript: true,
+            ..Default::default()
+        },
+    );
+    assert!(rendered.contains("mc<sup>2</sup>"));
+}
+
+#[test]
+fn config_overrides_only_the_extensions_it_names() {
+    use wiki::config::{Config, MarkdownExtensions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let missing = Config::load(dir.path().join("wiki.toml")).unwrap();
+    assert_eq!(missing.markdown, MarkdownExtensions::default());
+
+    let path = dir.path().join("wiki.toml");
+    std::fs::write(
+        &path,
+        "[markdown]\nfootnotes = false\ndescription_lists = true\n",
+    )
+    .unwrap();
+    let config = Config::load(&path).unwrap();
+    assert!(!config.markdown.footnotes);
+    assert!(config.markdown.description_lists);
+    assert!(config.markdown.table);
+
+    std::fs::write(&path, "[markdown]\ntables = true\n").unwrap();
+    assert!(Config::load(&path).is_err());
+}
+
+#[tokio::test]
+async fn serve_docs_renders_with_the_configured_extensions() {
+    let (_dir, root, service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(format!("{}/old.md", root), "~~retired~~\n").unwrap();
+
+    let mut service = service.with_markdown_extensions(wiki::config::MarkdownExtensions {
+        strikethrough: false,
+        ..Default::default()
+    });
+    let page = body_text(send(&mut service, "GET", "/old", 1, None).await).await;
+    assert!(page.contains("~~retired~~"));
+
+    // Links are read with the same extensions: without math, this is a wiki link.
+    let mut service = service.with_markdown_extensions(wiki::config::MarkdownExtensions {
+        math: false,
+        ..Default::default()
+    });
+    send(
+        &mut service,
+        "POST",
+        "/formulas?edit",
+        2,
+        Some(&with_base(
+            &root,
+            "formulas",
+            "content=%24%5B%5Bold%5D%5D%24",
+        )),
+    )
+    .await;
+    let page = body_text(send(&mut service, "GET", "/old", 1, None).await).await;
+    assert!(page.contains("<a href=\"/docs/formulas\">formulas</a>"));
+}
+
+#[test]
+fn sanitize_strips_scripts_handlers_and_hostile_urls() {
+    use wiki::docs::sanitize::sanitize;
+
+    let payloads = [
+        "<script>alert(1)</script>",
+        "<img src=\"x\" onerror=\"alert(1)\">",
+        "<a href=\"javascript:alert(1)\">x</a>",
+        "<a href=\"data:text/html;base64,PHNjcmlwdD4=\">x</a>",
+        "<iframe src=\"https://evil.example\"></iframe>",
+        "<svg onload=\"alert(1)\"></svg>",
+        "<p style=\"background:url(javascript:alert(1))\">x</p>",
+        "<input type=\"text\" autofocus onfocus=\"alert(1)\">",
+    ];
+    for payload in payloads {
+        let cleaned = sanitize(payload);
+        for needle in [
+            "<script",
+            "alert",
+            "javascript:",
+            "data:",
+            "<iframe",
+            "<svg",
+            "style=",
+            "text",
+        ] {
+            assert!(
+                !cleaned.contains(needle),
+                "{:?} survived as {:?}",
+                payload,
+                cleaned
+            );
+        }
+    }
+
+    // What the renderer itself produces is left alone.
+    let safe = "<h2 id=\"setup\">Setup</h2><p><a href=\"/docs/apples\" data-wikilink=\"true\">Apples</a></p>";
+    assert_eq!(sanitize(safe), safe);
+}
+
+#[test]
+fn rendered_markdown_neutralizes_xss_payloads() {
+    let doc = "<script>alert(1)</script>\n\n[click](javascript:alert(1)) ![img](javascript:alert(1)) <javascript:alert(1)>\n\n<img src=x onerror=alert(1)>\n\n[[\" onmouseover=\"alert(1)]]\n\n{!2 <b onclick=\"alert(1)\">x</b>}\n";
+    for permissions in [1, 2] {
+        let rendered = parse_markdown(doc, permissions);
+        assert!(!rendered.contains("<script"), "{}", rendered);
+        assert!(!rendered.contains("=\"javascript:"), "{}", rendered);
+        assert!(!rendered.contains("onerror"), "{}", rendered);
+        assert!(!rendered.contains("onclick"), "{}", rendered);
+        assert!(
+            !rendered.contains("onmouseover=\"alert(1)\""),
+            "{}",
+            rendered
+        );
+    }
+}
+
+#[tokio::test]
+async fn serve_docs_escapes_page_contents_in_the_editor() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        format!("{}/trap.md", root),
+        "</textarea><script>alert(1)</script>\n",
+    )
+    .unwrap();
+
+    let form = body_text(send(&mut service, "GET", "/trap?edit", 2, None).await).await;
+    assert!(!form.contains("<script>"));
+    assert!(form.contains("&lt;/textarea&gt;&lt;script&gt;alert(1)&lt;/script&gt;"));
+
+    let page = body_text(send(&mut service, "GET", "/trap", 1, None).await).await;
+    assert!(!page.contains("<script>alert(1)"));
+}
+
+fn render_including(doc: &str, permissions: i32, includes: &[(&str, &str)]) -> String {
+    use wiki::docs::{RenderContext, render_markdown};
+
+    let context = RenderContext {
+        page: Some("handbook".into()),
+        includes: includes
+            .iter()
+            .map(|(page, doc)| (page.to_string(), doc.to_string()))
+            .collect(),
+        ..RenderContext::default()
+    };
+    render_markdown(doc, permissions, &[], &context)
+}
+
+#[test]
+fn includes_render_the_included_page_with_its_own_gates() {
+    let doc = "# Handbook\n\n{{include: shared/oncall}}\n\nAfterwards\n";
+    let oncall = "---\ntitle: On-call\n---\nPage the *on-call*.\n!3\nPager PIN 1234\n!end\nEscalate after {!2 ten} minutes.\n";
+    let includes = [("shared/oncall", oncall)];
+
+    let guest = render_including(doc, 1, &includes);
+    assert!(guest.contains("Page the <em>on-call</em>."));
+    assert!(guest.contains("Afterwards"));
+    assert!(!guest.contains("title: On-call"));
+    assert!(!guest.contains("Pager PIN"));
+    assert!(!guest.contains("ten"));
+    assert!(!guest.contains("{{include"));
+
+    let senior = render_including(doc, 3, &includes);
+    assert!(senior.contains("Pager PIN 1234"));
+    assert!(senior.contains("Escalate after ten minutes."));
+}
+
+#[test]
+fn includes_inside_gated_sections_follow_the_gate() {
+    let doc = "Public\n!3\n{{include: secrets}}\n!end\n";
+    let includes = [("secrets", "Launch codes\n")];
+
+    assert!(!render_including(doc, 1, &includes).contains("Launch codes"));
+    assert!(render_including(doc, 3, &includes).contains("Launch codes"));
+}
+
+#[test]
+fn include_cycles_and_missing_pages_render_errors() {
+    let cycle = render_including(
+        "{{include: a}}\n",
+        1,
+        &[
+            ("a", "A\n\n{{include: b}}\n"),
+            ("b", "B\n\n{{include: a}}\n"),
+        ],
+    );
+    assert!(cycle.contains("<p>A</p>"));
+    assert!(cycle.contains("<p>B</p>"));
+    assert!(cycle.contains("class=\"include-error\""));
+    assert!(cycle.contains("Include cycle: a → b → a."));
+
+    let own = render_including("Intro\n\n{{include: handbook}}\n", 1, &[]);
+    assert!(own.contains("Include cycle: handbook → handbook."));
+
+    let missing = render_including("{{include: ../etc/passwd}}\n{{include: nowhere}}\n", 1, &[]);
+    assert!(missing.contains("Cannot include “../etc/passwd”: not a page name."));
+    assert!(missing.contains("Cannot include “nowhere”: no such page"));
+}
+
+#[test]
+fn include_directives_in_code_fences_are_left_alone() {
+    let rendered = render_including(
+        "```\n{{include: secrets}}\n```\n",
+        1,
+        &[("secrets", "Launch codes\n")],
+    );
+    assert!(rendered.contains("{{include: secrets}}"));
+    assert!(!rendered.contains("Launch codes"));
+}
+
+#[tokio::test]
+async fn serve_docs_includes_only_pages_the_reader_may_open() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(format!("{}/shared", root)).unwrap();
+    std::fs::create_dir_all(format!("{}/vault", root)).unwrap();
+    std::fs::write(format!("{}/vault/.access.toml", root), "read = 4\n").unwrap();
+    std::fs::write(format!("{}/vault/codes.md", root), "Launch codes\n").unwrap();
+    std::fs::write(
+        format!("{}/shared/oncall.md", root),
+        "Page the on-call.\n\n{{include: vault/codes}}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        format!("{}/handbook.md", root),
+        "# Handbook\n\n{{include: shared/oncall}}\n\n{{include: handbook}}\n",
+    )
+    .unwrap();
+
+    let guest = body_text(send(&mut service, "GET", "/handbook", 1, None).await).await;
+    assert!(guest.contains("Page the on-call."));
+    assert!(!guest.contains("Launch codes"));
+    assert!(guest.contains("Cannot include “vault/codes”"));
+    assert!(guest.contains("Include cycle: handbook → handbook."));
+
+    let admin = body_text(send(&mut service, "GET", "/handbook", 4, None).await).await;
+    assert!(admin.contains("Launch codes"));
+}
+
+#[tokio::test]
+async fn serve_docs_offers_templates_for_new_pages() {
+    let (dir, root, service) = docs_service();
+    let templates = dir.path().join("templates");
+    std::fs::create_dir_all(templates.join("teams")).unwrap();
+    std::fs::write(
+        templates.join("meeting.md"),
+        "# {{title}}\n\nHeld {{date}}\n",
+    )
+    .unwrap();
+    std::fs::write(templates.join("teams/charter.md"), "Charter\n").unwrap();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(format!("{}/existing.md", root), "Already here\n").unwrap();
+    let mut service = service.with_templates(templates.to_str().unwrap());
+
+    let blank = body_text(send(&mut service, "GET", "/notes/standup?edit", 2, None).await).await;
+    assert!(blank.contains("class=\"template-picker\""));
+    assert!(blank.contains("<option value=\"meeting\">meeting</option>"));
+    assert!(blank.contains("<option value=\"teams/charter\">teams/charter</option>"));
+    assert!(blank.contains("></textarea>"));
+
+    let prefilled = body_text(
+        send(
+            &mut service,
+            "GET",
+            "/notes/standup?edit=&template=meeting",
+            2,
+            None,
+        )
+        .await,
+    )
+    .await;
+    assert!(prefilled.contains("<option value=\"meeting\" selected>"));
+    assert!(prefilled.contains("# {{title}}\n\nHeld {{date}}\n</textarea>"));
+    assert!(prefilled.contains("<input type=\"hidden\" name=\"template\" value=\"meeting\">"));
+    assert!(!blank.contains("name=\"template\" value"));
+    // The base is the empty page on disk, not the template.
+    assert!(prefilled.contains(&wiki::docs::edit::content_hash("")));
+
+    let existing = body_text(send(&mut service, "GET", "/existing?edit", 2, None).await).await;
+    assert!(!existing.contains("template-picker"));
+
+    for template in ["nowhere", "../docs/existing", ".hidden"] {
+        let uri = format!("/notes/standup?edit&template={}", template);
+        let missing = send(&mut service, "GET", &uri, 2, None).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND, "{}", template);
+    }
+}
+
+#[tokio::test]
+async fn serve_docs_fills_template_variables_on_first_save_only() {
+    let (_dir, root, mut service) = docs_service();
+    let form = format!(
+        "content={}&base={}&template=meeting",
+        urlencoding::encode("# {{title}}\n\nHeld {{date}} by {{author}}\n"),
+        wiki::docs::edit::content_hash("")
+    );
+    let created = send(&mut service, "POST", "/notes/standup?edit", 2, Some(&form)).await;
+    assert_eq!(created.status(), StatusCode::SEE_OTHER);
+
+    let today = chrono::Utc::now().date_naive().format("%Y-%m-%d");
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes/standup.md", root)).unwrap(),
+        format!("# standup\n\nHeld {} by editor\n", today)
+    );
+
+    let form = with_base(
+        &root,
+        "notes/standup",
+        &format!(
+            "content={}",
+            urlencoding::encode("Use {{title}} in templates\n")
+        ),
+    );
+    let updated = send(&mut service, "POST", "/notes/standup?edit", 2, Some(&form)).await;
+    assert_eq!(updated.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes/standup.md", root)).unwrap(),
+        "Use {{title}} in templates\n"
+    );
+
+    // Pages not started from a template are saved as written.
+    let form = with_base(
+        &root,
+        "notes/syntax",
+        &format!(
+            "content={}",
+            urlencoding::encode("Write {{title}} for the title\n")
+        ),
+    );
+    let created = send(&mut service, "POST", "/notes/syntax?edit", 2, Some(&form)).await;
+    assert_eq!(created.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes/syntax.md", root)).unwrap(),
+        "Write {{title}} for the title\n"
+    );
+}
+
+#[test]
+fn math_renders_to_mathml_on_the_server() {
+    let doc = "Energy is $E = mc^2$, not $5 or $10.\n\n$$\n\\sum_{i=1}^{n} \\frac{1}{i^2} \\le \\frac{\\pi^2}{6}\n$$\n\n```math\n\\sqrt{x}\n```\n";
+    let rendered = parse_markdown(doc, 1);
+
+    assert!(rendered.contains("<math display=\"inline\"><semantics><mrow><mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup></mrow>"));
+    assert!(rendered.contains("<annotation encoding=\"application/x-tex\">E = mc^2</annotation>"));
+    assert!(rendered.contains("not $5 or $10."));
+    assert!(rendered.contains("<math display=\"block\">"));
+    assert!(rendered.contains("<munderover><mo>∑</mo>"));
+    assert!(rendered.contains(
+        "<mfrac><mrow><msup><mi>π</mi><mn>2</mn></msup></mrow><mrow><mn>6</mn></mrow></mfrac>"
+    ));
+    assert!(rendered.contains("<msqrt><mrow><mi>x</mi></mrow></msqrt>"));
+    assert!(!rendered.contains("data-math-style"));
+}
+
+#[test]
+fn math_covers_environments_and_flags_unknown_commands() {
+    use wiki::docs::math::to_mathml;
+
+    let cases = to_mathml(
+        "|x| = \\begin{cases} x & x \\ge 0 \\\\ -x & \\text{otherwise} \\end{cases}",
+        true,
+    );
+    assert!(cases.contains("<mo stretchy=\"true\">{</mo><mtable columnalign=\"left left\"><mtr><mtd><mi>x</mi></mtd><mtd><mi>x</mi><mo>≥</mo><mn>0</mn></mtd></mtr><mtr><mtd><mo>−</mo><mi>x</mi></mtd><mtd><mtext>otherwise</mtext></mtd></mtr></mtable>"));
+
+    let inline = to_mathml(
+        "\\left( \\alpha_1 \\right) \\in \\mathbb{R} \\frobnicate",
+        false,
+    );
+    assert!(inline.contains("<mrow><mo stretchy=\"true\">(</mo><msub><mi>α</mi><mn>1</mn></msub><mo stretchy=\"true\">)</mo></mrow>"));
+    assert!(inline.contains("<mi mathvariant=\"double-struck\">R</mi>"));
+    assert!(inline.contains("<merror><mtext>\\frobnicate</mtext></merror>"));
+
+    // Unbalanced input still produces well-formed MathML.
+    let broken = to_mathml("\\frac{a}{ } } \\end{cases} x_", false);
+    assert!(broken.starts_with("<math"));
+    assert!(broken.ends_with("</math>"));
+}
+
+#[test]
+fn math_cannot_smuggle_markup() {
+    let rendered = parse_markdown(
+        "$<script>alert(1)</script>$ and $\\text{<img src=x onerror=alert(1)>}$\n",
+        1,
+    );
+    assert!(!rendered.contains("<script"), "{}", rendered);
+    assert!(!rendered.contains("<img"), "{}", rendered);
+    assert!(rendered.contains("<mtext>&lt;img src=x onerror=alert(1)&gt;</mtext>"));
+}
+
+#[test]
+fn math_renders_overly_deep_nesting_as_an_error() {
+    use wiki::docs::math::to_mathml;
+
+    let deep = |open: &str, close: &str, depth: usize| {
+        format!("{}x{}", open.repeat(depth), close.repeat(depth))
+    };
+    for tex in [
+        deep("{", "}", 100_000),
+        deep("\\frac{", "}{y}", 10_000),
+        deep("\\left(", "\\right)", 10_000),
+        deep("\\sqrt[", "]", 10_000),
+    ] {
+        let rendered = to_mathml(&tex, false);
+        assert!(rendered.contains("<merror>"), "{}", &tex[..20]);
+    }
+
+    let shallow = to_mathml(&deep("{", "}", 30), false);
+    assert!(!shallow.contains("<merror>"));
+    assert!(shallow.contains("<mi>x</mi>"));
+}
+
+#[test]
+fn math_is_left_as_text_when_disabled() {
+    let extensions = wiki::config::MarkdownExtensions {
+        math: false,
+        ..Default::default()
+    };
+    let rendered = render_with("Energy is $E = mc^2$.\n", extensions);
+    assert!(rendered.contains("Energy is $E = mc^2$."));
+    assert!(!rendered.contains("<math"));
+}
+
+#[tokio::test]
+async fn serve_docs_renders_gated_pages_from_the_session_cookie() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        format!("{}/ledger.md", root),
+        "Public intro\n!3\nQuarterly numbers\n!end\n",
+    )
+    .unwrap();
+    let token = bearer(3).trim_start_matches("Bearer ").to_string();
+
+    let get = |cookie: String, authorization: Option<String>| {
+        let mut request = Request::builder().uri("/ledger").header("Cookie", cookie);
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let first = service
+        .call(get(format!("theme=dark; wiki_session={}", token), None))
+        .await
+        .unwrap();
+    let page = body_text(first).await;
+    assert!(page.contains("Quarterly numbers"));
+    assert!(!page.contains("redirectToLogin"));
+
+    // An explicit bearer token wins over the cookie.
+    let guest = service
+        .call(get(
+            format!("wiki_session={}", token),
+            Some("Bearer guest".into()),
+        ))
+        .await
+        .unwrap();
+    assert!(!body_text(guest).await.contains("Quarterly numbers"));
+
+    // A stale or forged cookie gets the bootstrap page, which can refresh it.
+    let stale = service
+        .call(get("wiki_session=expired.or.forged".into(), None))
+        .await
+        .unwrap();
+    let bootstrap = body_text(stale).await;
+    assert!(bootstrap.contains("/api/refresh"));
+    assert!(!bootstrap.contains("Quarterly numbers"));
+}
+
+/// An access token for session `sid`, as `/api/login` would put in the session cookie.
+fn session_cookie(sid: i64) -> String {
+    use wiki::user::get_current_timestamp;
+
+    #[derive(serde::Serialize)]
+    struct Claims {
+        sub: String,
+        privileges: i32,
+        sid: i64,
+        exp: u64,
+    }
+
+    let claims = Claims {
+        sub: "editor".into(),
+        privileges: 2,
+        sid,
+        exp: get_current_timestamp() + 3600,
+    };
+    let token = wiki::KEYS.encode(&claims).expect("failed to encode token");
+    format!("wiki_session={}", token)
+}
+
+async fn send_with_cookie(
+    service: &mut wiki::docs::ServeDocs,
+    method: &str,
+    uri: &str,
+    cookie: &str,
+    form: Option<&str>,
+) -> axum::response::Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Cookie", cookie)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(form.map(|f| Body::from(f.to_string())).unwrap_or_default())
+        .unwrap();
+    service.call(request).await.unwrap()
+}
+
+/// The CSRF token embedded in a rendered form.
+fn csrf_token(html: &str) -> String {
+    let start = html
+        .find("name=\"csrf_token\" value=\"")
+        .expect("form should carry a CSRF token")
+        + "name=\"csrf_token\" value=\"".len();
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_string()
+}
+
+#[tokio::test]
+async fn serve_docs_rejects_cookie_edits_without_the_sessions_csrf_token() {
+    let (_dir, root, mut service) = docs_service();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(format!("{}/notes.md", root), "Original").unwrap();
+    let cookie = session_cookie(1);
+
+    let form = send_with_cookie(&mut service, "GET", "/notes?edit", &cookie, None).await;
+    let token = csrf_token(&body_text(form).await);
+
+    let other_session = csrf_token(
+        &body_text(
+            send_with_cookie(&mut service, "GET", "/notes?edit", &session_cookie(2), None).await,
+        )
+        .await,
+    );
+    assert_ne!(token, other_session);
+
+    for forged in [
+        "content=Forged".to_string(),
+        "content=Forged&csrf_token=".to_string(),
+        "content=Forged&csrf_token=not-a-token".to_string(),
+        format!("content=Forged&csrf_token={}", other_session),
+    ] {
+        let response =
+            send_with_cookie(&mut service, "POST", "/notes?edit", &cookie, Some(&forged)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", forged);
+    }
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes.md", root)).unwrap(),
+        "Original"
+    );
+
+    let response = send_with_cookie(
+        &mut service,
+        "POST",
+        "/notes?edit",
+        &cookie,
+        Some(&with_base(
+            &root,
+            "notes",
+            &format!("content=Updated&csrf_token={}", token),
+        )),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes.md", root)).unwrap(),
+        "Updated"
+    );
+}
+
+#[tokio::test]
+async fn serve_docs_accepts_the_csrf_token_in_a_header() {
+    let (_dir, root, mut service) = docs_service();
+    let cookie = session_cookie(7);
+    let form = send_with_cookie(&mut service, "GET", "/notes?edit", &cookie, None).await;
+    let token = csrf_token(&body_text(form).await);
+
+    let post = |csrf: Option<&str>| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/notes?edit")
+            .header("Cookie", cookie.as_str())
+            .header("content-type", "application/json");
+        if let Some(csrf) = csrf {
+            request = request.header("X-CSRF-Token", csrf);
+        }
+        request
+            .body(Body::from(format!(
+                r#"{{"content": "From a script", "base": "{}"}}"#,
+                wiki::docs::edit::content_hash("")
+            )))
+            .unwrap()
+    };
+
+    let forged = service.call(post(None)).await.unwrap();
+    assert_eq!(forged.status(), StatusCode::FORBIDDEN);
+    let wrong = service.call(post(Some("not-a-token"))).await.unwrap();
+    assert_eq!(wrong.status(), StatusCode::FORBIDDEN);
+    assert!(!std::path::Path::new(&format!("{}/notes.md", root)).exists());
+
+    let response = service.call(post(Some(&token))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/notes.md", root)).unwrap(),
+        "From a script"
+    );
+}
+
+#[tokio::test]
+async fn serve_docs_revert_form_carries_a_csrf_token() {
+    let (_dir, root, mut service) = docs_service();
+    send(
+        &mut service,
+        "POST",
+        "/runbook?edit",
+        2,
+        Some(&with_base(&root, "runbook", "content=First")),
+    )
+    .await;
+    send(
+        &mut service,
+        "POST",
+        "/runbook?edit",
+        2,
+        Some(&with_base(&root, "runbook", "content=Second")),
+    )
+    .await;
+    let cookie = session_cookie(3);
+
+    let form = send_with_cookie(&mut service, "GET", "/runbook?revert=1", &cookie, None).await;
+    let token = csrf_token(&body_text(form).await);
+
+    let forged = send_with_cookie(&mut service, "POST", "/runbook?revert=1", &cookie, None).await;
+    assert_eq!(forged.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/runbook.md", root)).unwrap(),
+        "Second"
+    );
+
+    let response = send_with_cookie(
+        &mut service,
+        "POST",
+        "/runbook?revert=1",
+        &cookie,
+        Some(&format!("csrf_token={}", token)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/runbook.md", root)).unwrap(),
+        "First"
+    );
+}
@@ -1,8 +1,10 @@
 use axum::body::{Body, to_bytes};
-use axum::http::{header, Request, StatusCode};
-use serde_json::{json, Value};
-use tokio::time::{timeout, Duration};
+use axum::http::{Request, StatusCode, header};
+use serde_json::{Value, json};
+use tokio::time::{Duration, timeout};
 use tower::ServiceExt;
+use wiki::config::{MailConfig, MailTransport};
+use wiki::mail::Mailer;
 
 #[tokio::test]
 async fn app_router_serves_docs_bootstrap_without_auth() {
@@ -50,8 +52,15 @@ async fn register_returns_token_and_privileges() {
         assert_json_response(&response);
 
         let body = to_body_json(response).await;
-        assert!(body.get("token").and_then(Value::as_str).unwrap().len() > 0);
+        assert!(
+            !body
+                .get("token")
+                .and_then(Value::as_str)
+                .unwrap()
+                .is_empty()
+        );
         assert_eq!(body.get("privileges").and_then(Value::as_i64), Some(1));
+        assert_eq!(body.get("roles"), Some(&json!(["level-1"])));
     })
     .await;
 }
@@ -70,7 +79,13 @@ async fn login_returns_token_for_valid_credentials() {
         assert_eq!(response.status(), StatusCode::OK);
         assert_json_response(&response);
         let body = to_body_json(response).await;
-        assert!(body.get("token").and_then(Value::as_str).unwrap().len() > 0);
+        assert!(
+            !body
+                .get("token")
+                .and_then(Value::as_str)
+                .unwrap()
+                .is_empty()
+        );
         assert_eq!(body.get("privileges").and_then(Value::as_i64), Some(1));
 
         let bad_login = login(&username, bad_password).await;
@@ -94,6 +109,483 @@ async fn register_duplicate_username_returns_error() {
     .await;
 }
 
+#[tokio::test]
+async fn search_api_returns_readable_pages() {
+    with_timeout(async {
+        let page = unique_username("search-api");
+        let word = page.replace('-', "");
+        wiki::DB
+            .index_page(
+                &page,
+                vec![
+                    (0.into(), format!("public {}\n", word)),
+                    (5.into(), format!("restricted {} details\n", word)),
+                ],
+            )
+            .await
+            .expect("index page");
+
+        let response = call(
+            Request::builder()
+                .uri(format!("/api/search?q={}", word))
+                .body(Body::empty())
+                .expect("search request"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_json_response(&response);
+
+        let body = to_body_json(response).await;
+        let results = body.as_array().expect("results array");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["url"], format!("/docs/{}", page));
+        assert!(
+            !results[0]["snippet"]
+                .as_str()
+                .unwrap()
+                .contains("restricted")
+        );
+
+        let page_response = call(
+            Request::builder()
+                .uri(format!("/docs?search={}", word))
+                .header("Authorization", "Bearer guest")
+                .body(Body::empty())
+                .expect("search page request"),
+        )
+        .await;
+        assert_eq!(page_response.status(), StatusCode::OK);
+        let html = to_bytes(page_response.into_body(), 1 << 20)
+            .await
+            .expect("read body");
+        let html = String::from_utf8(html.to_vec()).expect("body utf8");
+        assert!(html.contains(&format!("href=\"/docs/{}\"", page)));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn backlinks_api_hides_links_from_gated_sections() {
+    with_timeout(async {
+        let target = unique_username("backlinks-target");
+        let public_source = format!("{}-public", target);
+        let gated_source = format!("{}-gated", target);
+        wiki::DB
+            .replace_links(&public_source, vec![(1.into(), target.clone())])
+            .await
+            .expect("replace links");
+        wiki::DB
+            .replace_links(&gated_source, vec![(5.into(), target.clone())])
+            .await
+            .expect("replace links");
+
+        let response = call(
+            Request::builder()
+                .uri(format!("/api/docs/{}/backlinks", target))
+                .body(Body::empty())
+                .expect("backlinks request"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_json_response(&response);
+
+        let body = to_body_json(response).await;
+        assert_eq!(
+            body,
+            json!([{ "page": public_source, "url": format!("/docs/{}", public_source) }])
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn page_api_returns_front_matter() {
+    with_timeout(async {
+        let response = call(
+            Request::builder()
+                .uri("/api/docs/apples")
+                .body(Body::empty())
+                .expect("page request"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_json_response(&response);
+
+        let body = to_body_json(response).await;
+        assert_eq!(body["page"], "apples");
+        assert_eq!(body["url"], "/docs/apples");
+        assert_eq!(body["title"], "Apples");
+        assert_eq!(body["tags"], json!(["examples"]));
+
+        let missing = call(
+            Request::builder()
+                .uri("/api/docs/no-such-page")
+                .body(Body::empty())
+                .expect("page request"),
+        )
+        .await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let outside = call(
+            Request::builder()
+                .uri("/api/docs/..%2FCargo")
+                .body(Body::empty())
+                .expect("page request"),
+        )
+        .await;
+        assert_eq!(outside.status(), StatusCode::NOT_FOUND);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn tags_api_counts_tagged_pages() {
+    with_timeout(async {
+        let tag = unique_username("tag");
+        for page in ["first", "second"] {
+            wiki::DB
+                .replace_tags(&format!("{}-{}", tag, page), vec![tag.clone()])
+                .await
+                .expect("replace tags");
+        }
+
+        let response = call(
+            Request::builder()
+                .uri("/api/tags")
+                .body(Body::empty())
+                .expect("tags request"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_json_response(&response);
+
+        let body = to_body_json(response).await;
+        let entry = body
+            .as_array()
+            .expect("tags array")
+            .iter()
+            .find(|entry| entry["tag"] == tag.as_str())
+            .expect("tag listed");
+        assert_eq!(entry["count"], 2);
+        assert_eq!(entry["url"], format!("/docs?tag={}", tag));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn login_issues_short_lived_access_and_rotating_refresh_tokens() {
+    let username = unique_username("refresh");
+    let body =
+        with_timeout(async { to_body_json(register(&username, "secret").await).await }).await;
+    let first = body["refresh_token"].as_str().unwrap().to_string();
+    assert_eq!(
+        body["expires_in"].as_u64(),
+        Some(wiki::user::ACCESS_TOKEN_LIFETIME)
+    );
+    let claims = wiki::user::decode_jwt(body["token"].as_str().unwrap()).unwrap();
+    assert!(claims.exp <= wiki::user::get_current_timestamp() + wiki::user::ACCESS_TOKEN_LIFETIME);
+
+    let refreshed = with_timeout(refresh(&first)).await;
+    assert_eq!(refreshed.status(), StatusCode::OK);
+    let refreshed = to_body_json(refreshed).await;
+    let second = refreshed["refresh_token"].as_str().unwrap().to_string();
+    assert_ne!(first, second);
+    assert_eq!(refreshed["privileges"].as_i64(), Some(1));
+    assert_eq!(
+        wiki::user::decode_jwt(refreshed["token"].as_str().unwrap())
+            .unwrap()
+            .sub,
+        username
+    );
+
+    // A refresh token works once; replaying it ends the session it belonged to.
+    let replayed = with_timeout(refresh(&first)).await;
+    assert_eq!(replayed.status(), StatusCode::UNAUTHORIZED);
+    let after_replay = with_timeout(refresh(&second)).await;
+    assert_eq!(after_replay.status(), StatusCode::UNAUTHORIZED);
+
+    let garbage = with_timeout(refresh("not-a-token")).await;
+    assert_eq!(garbage.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn logout_ends_one_session_or_all_of_them() {
+    let username = unique_username("logout");
+    let mut sessions = Vec::new();
+    for attempt in 0..3 {
+        let response = if attempt == 0 {
+            register(&username, "secret").await
+        } else {
+            login(&username, "secret").await
+        };
+        let body = to_body_json(response).await;
+        sessions.push(body["refresh_token"].as_str().unwrap().to_string());
+    }
+
+    let logged_out = with_timeout(logout(&sessions[0], false)).await;
+    assert_eq!(logged_out.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        with_timeout(refresh(&sessions[0])).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        with_timeout(logout(&sessions[0], false)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // The second session is still live until it logs out everywhere.
+    let rotated = to_body_json(with_timeout(refresh(&sessions[1])).await).await;
+    let current = rotated["refresh_token"].as_str().unwrap();
+    let everywhere = with_timeout(logout(current, true)).await;
+    assert_eq!(everywhere.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        with_timeout(refresh(&sessions[2])).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn logout_everywhere_works_with_the_refresh_cookie_alone() {
+    let username = unique_username("logout-cookie");
+    let first = set_cookies(&with_timeout(register(&username, "secret")).await);
+    let second = set_cookies(&with_timeout(login(&username, "secret")).await);
+    let first = cookie_value(&first, "wiki_refresh");
+    let second = cookie_value(&second, "wiki_refresh");
+
+    let logged_out = with_timeout(call(
+        Request::builder()
+            .method("POST")
+            .uri("/api/logout?everywhere=true")
+            .header(header::COOKIE, format!("wiki_refresh={}", first))
+            .body(Body::empty())
+            .expect("logout request"),
+    ))
+    .await;
+    assert_eq!(logged_out.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        with_timeout(refresh(&second)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn login_sets_http_only_session_cookies() {
+    let username = unique_username("cookies");
+    let registered = with_timeout(register(&username, "secret")).await;
+    let cookies = set_cookies(&registered);
+    let session = cookie_value(&cookies, "wiki_session");
+    let refresh_token = cookie_value(&cookies, "wiki_refresh");
+    assert!(
+        cookies
+            .iter()
+            .all(|cookie| cookie.contains("; HttpOnly") && cookie.contains("; SameSite="))
+    );
+    assert!(
+        cookies
+            .iter()
+            .any(|cookie| cookie.starts_with("wiki_refresh=") && cookie.contains("Path=/api;"))
+    );
+    let body = to_body_json(registered).await;
+    assert_eq!(body["token"].as_str(), Some(session.as_str()));
+
+    // The docs render straight away for a browser holding the cookie.
+    let page = with_timeout(call(
+        Request::builder()
+            .uri("/docs/apples")
+            .header(header::COOKIE, format!("wiki_session={}", session))
+            .body(Body::empty())
+            .expect("docs request"),
+    ))
+    .await;
+    assert_eq!(page.status(), StatusCode::OK);
+    let page = to_bytes(page.into_body(), 1 << 20).await.unwrap();
+    assert!(!String::from_utf8_lossy(&page).contains("redirectToLogin"));
+
+    // The refresh cookie alone is enough to refresh and to log out.
+    let refreshed = with_timeout(call(
+        Request::builder()
+            .method("POST")
+            .uri("/api/refresh")
+            .header(header::COOKIE, format!("wiki_refresh={}", refresh_token))
+            .body(Body::empty())
+            .expect("refresh request"),
+    ))
+    .await;
+    assert_eq!(refreshed.status(), StatusCode::OK);
+    let rotated = cookie_value(&set_cookies(&refreshed), "wiki_refresh");
+    assert_ne!(rotated, refresh_token);
+
+    let logged_out = with_timeout(call(
+        Request::builder()
+            .method("POST")
+            .uri("/api/logout")
+            .header(header::COOKIE, format!("wiki_refresh={}", rotated))
+            .body(Body::empty())
+            .expect("logout request"),
+    ))
+    .await;
+    assert_eq!(logged_out.status(), StatusCode::NO_CONTENT);
+    let cleared = set_cookies(&logged_out);
+    assert_eq!(cookie_value(&cleared, "wiki_session"), "");
+    assert!(cleared.iter().all(|cookie| cookie.contains("Max-Age=0")));
+    assert_eq!(
+        with_timeout(refresh(&rotated)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn password_reset_mails_a_single_use_link() {
+    let mailer: &'static Mailer = Box::leak(Box::new(
+        Mailer::new(&MailConfig {
+            transport: MailTransport::Memory,
+            ..Default::default()
+        })
+        .expect("memory mailer"),
+    ));
+    let forgot = |body: Value| {
+        let request = json_request("/api/password/forgot", body);
+        wiki::app::router_with_mailer(mailer).oneshot(request)
+    };
+    let username = unique_username("reset");
+    let email = format!("{}@example.com", username);
+    let registered = with_timeout(call(json_request(
+        "/api/register",
+        json!({ "username": username, "password": "forgotten", "email": email.to_uppercase() }),
+    )))
+    .await;
+    assert_eq!(registered.status(), StatusCode::OK);
+    let session = to_body_json(registered).await["refresh_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let invalid = with_timeout(forgot(json!({ "email": "not an address" })))
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    let unknown = with_timeout(forgot(json!({ "email": format!("nobody-{}", email) })))
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), StatusCode::ACCEPTED);
+
+    let accepted = with_timeout(forgot(json!({ "email": email })))
+        .await
+        .unwrap();
+    assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+    let token = mailed_reset_token(mailer, &email).await;
+
+    let reset = |token: &str, password: &str| {
+        call(json_request(
+            "/api/password/reset",
+            json!({ "token": token, "password": password }),
+        ))
+    };
+    assert_eq!(
+        with_timeout(reset("not-a-token", "chosen")).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        with_timeout(reset(&token, "chosen")).await.status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        with_timeout(reset(&token, "again")).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    assert_eq!(
+        with_timeout(login(&username, "forgotten")).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        with_timeout(login(&username, "chosen")).await.status(),
+        StatusCode::OK
+    );
+    // Sessions opened with the old password are over.
+    assert_eq!(
+        with_timeout(refresh(&session)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// The token in the reset link `mailer` sent to `email`. Mail goes out in the background,
+/// so this waits for it.
+async fn mailed_reset_token(mailer: &Mailer, email: &str) -> String {
+    for _ in 0..100 {
+        for message in mailer.sent().await {
+            if !message.contains(&format!("To: {}", email)) {
+                continue;
+            }
+            // Long lines are quoted-printable encoded.
+            let body = message.replace("=\r\n", "").replace("=3D", "=");
+            let (_, rest) = body
+                .split_once("?token=")
+                .expect("message has a reset link");
+            return rest.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("no reset link was mailed to {}", email);
+}
+
+fn json_request(uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("json request")
+}
+
+fn set_cookies(response: &axum::response::Response) -> Vec<String> {
+    response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .collect()
+}
+
+fn cookie_value(cookies: &[String], name: &str) -> String {
+    cookies
+        .iter()
+        .find_map(|cookie| {
+            let (pair, _) = cookie.split_once(';')?;
+            let (key, value) = pair.split_once('=')?;
+            (key == name).then(|| value.to_string())
+        })
+        .unwrap_or_else(|| panic!("no {} cookie in {:?}", name, cookies))
+}
+
+async fn refresh(refresh_token: &str) -> axum::response::Response {
+    call(
+        Request::builder()
+            .method("POST")
+            .uri("/api/refresh")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "refresh_token": refresh_token }).to_string(),
+            ))
+            .expect("refresh request"),
+    )
+    .await
+}
+
+async fn logout(refresh_token: &str, everywhere: bool) -> axum::response::Response {
+    call(
+        Request::builder()
+            .method("POST")
+            .uri("/api/logout")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "refresh_token": refresh_token, "everywhere": everywhere }).to_string(),
+            ))
+            .expect("logout request"),
+    )
+    .await
+}
+
 fn unique_username(prefix: &str) -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let nanos = SystemTime::now()
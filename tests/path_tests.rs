@@ -0,0 +1,209 @@
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode};
+use tower_service::Service;
+use wiki::docs::paths::{directory, normalize, page_file, page_key};
+
+/// Paths that must never resolve to a page, however they are spelled.
+const HOSTILE: &[&str] = &[
+    "/../secret",
+    "/plans/../../secret",
+    "/%2e%2e/secret",
+    "/%2E%2E/secret",
+    "/..%2fsecret",
+    "/..%2Fsecret",
+    "/plans/..%2f..%2fsecret",
+    "/%2e%2e%2f%2e%2e%2fsecret",
+    "/..%5csecret",
+    "/plans%5c..%5c..%5csecret",
+    "/secret%00",
+    "/%ff%fe",
+    "/.access.toml",
+    "/plans/.access",
+    "/.secret.md.123.0.tmp",
+];
+
+fn bearer(privileges: i32) -> String {
+    use wiki::user::get_current_timestamp;
+
+    #[derive(serde::Serialize)]
+    struct Claims {
+        sub: String,
+        privileges: i32,
+        exp: u64,
+    }
+
+    let claims = Claims {
+        sub: "editor".into(),
+        privileges,
+        exp: get_current_timestamp() + 3600,
+    };
+    let token = wiki::KEYS.encode(&claims).expect("failed to encode token");
+    format!("Bearer {}", token)
+}
+
+/// A docs root at `<tmp>/docs` with a page, and a `secret.md` just outside it.
+fn fixture() -> (tempfile::TempDir, String, wiki::docs::ServeDocs) {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let root = dir.path().join("docs");
+    std::fs::create_dir_all(root.join("plans")).unwrap();
+    std::fs::write(root.join("plans/roadmap.md"), "Roadmap\n").unwrap();
+    std::fs::write(dir.path().join("secret.md"), "TOP SECRET\n").unwrap();
+
+    let root = root.to_str().unwrap().to_string();
+    let database = wiki::db::Database::new(dir.path().join("wiki.sqlite").to_str().unwrap())
+        .expect("failed to create db");
+    let service = wiki::docs::ServeDocs::new(&root).with_database(database);
+    (dir, root, service)
+}
+
+async fn send(
+    service: &mut wiki::docs::ServeDocs,
+    method: &str,
+    uri: &str,
+    form: Option<&str>,
+) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", bearer(0))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(form.map(|f| Body::from(f.to_string())).unwrap_or_default())
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), 1 << 20).await.unwrap();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+#[test]
+fn page_keys_are_decoded_and_normalized() {
+    assert_eq!(
+        page_key("/plans/serve%20docs").as_deref(),
+        Some("plans/serve docs")
+    );
+    assert_eq!(page_key("/").as_deref(), Some(""));
+    assert_eq!(page_key("").as_deref(), Some(""));
+    assert_eq!(
+        page_key("/./plans//roadmap/").as_deref(),
+        Some("plans/roadmap")
+    );
+    // Escapes are decoded once, so a double-encoded dot-dot is just an odd file name.
+    assert_eq!(page_key("/%252e%252e").as_deref(), Some("%2e%2e"));
+    assert_eq!(
+        normalize("plans/serve docs").as_deref(),
+        Some("plans/serve docs")
+    );
+}
+
+#[test]
+fn hostile_page_keys_are_refused() {
+    for path in HOSTILE {
+        assert_eq!(page_key(path), None, "{} was accepted", path);
+    }
+    for path in ["..", "a/../b", "a\\b", "/etc/../passwd", ".hidden"] {
+        assert_eq!(normalize(path), None, "{} was accepted", path);
+    }
+}
+
+#[tokio::test]
+async fn page_files_stay_inside_the_root() {
+    let (dir, root, _service) = fixture();
+
+    assert_eq!(
+        page_file(&root, "plans/roadmap").await,
+        Some(dir.path().join("docs/plans/roadmap.md"))
+    );
+    // Pages that do not exist yet still resolve, so they can be created.
+    assert_eq!(
+        page_file(&root, "plans/new/page").await,
+        Some(dir.path().join("docs/plans/new/page.md"))
+    );
+    assert_eq!(page_file(&root, "").await, None);
+    assert_eq!(page_file(&root, "../secret").await, None);
+    assert_eq!(page_file(&root, "/../secret").await, None);
+
+    assert_eq!(directory(&root, "").await, Some(dir.path().join("docs/")));
+    assert_eq!(
+        directory(&root, "plans").await,
+        Some(dir.path().join("docs/plans"))
+    );
+    assert_eq!(directory(&root, "plans/roadmap").await, None);
+    assert_eq!(directory(&root, "..").await, None);
+}
+
+#[tokio::test]
+async fn hostile_paths_are_refused_by_every_endpoint() {
+    let (dir, _root, mut service) = fixture();
+
+    for path in HOSTILE {
+        for query in ["", "?edit", "?history", "?diff=1..2", "?revert=1"] {
+            let uri = format!("{}{}", path, query);
+            let (status, body) = send(&mut service, "GET", &uri, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "GET {}", uri);
+            assert!(!body.contains("TOP SECRET"), "GET {} leaked", uri);
+        }
+
+        let uri = format!("{}?edit", path);
+        let (status, _) = send(&mut service, "POST", &uri, Some("content=pwned")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "POST {}", uri);
+    }
+
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("secret.md")).unwrap(),
+        "TOP SECRET\n"
+    );
+    let stray: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with("wiki.sqlite"))
+        .collect();
+    assert_eq!(stray.len(), 2, "unexpected files: {:?}", stray);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_cannot_lead_out_of_the_root() {
+    use std::os::unix::fs::symlink;
+
+    let (dir, root, mut service) = fixture();
+    let outside = dir.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("notes.md"), "TOP SECRET notes\n").unwrap();
+    symlink(dir.path().join("secret.md"), format!("{}/leak.md", root)).unwrap();
+    symlink(&outside, format!("{}/linked", root)).unwrap();
+
+    for uri in ["/leak", "/linked/notes", "/linked/", "/linked/notes?edit"] {
+        let (status, body) = send(&mut service, "GET", uri, None).await;
+        assert!(
+            status == StatusCode::NOT_FOUND || status == StatusCode::BAD_REQUEST,
+            "GET {} answered {}",
+            uri,
+            status
+        );
+        assert!(!body.contains("TOP SECRET"), "GET {} leaked", uri);
+    }
+
+    let (status, _) = send(
+        &mut service,
+        "POST",
+        "/linked/new?edit",
+        Some("content=pwned"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!outside.join("new.md").exists());
+
+    let (status, _) = send(&mut service, "POST", "/leak?edit", Some("content=pwned")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("secret.md")).unwrap(),
+        "TOP SECRET\n"
+    );
+
+    // Symlinked pages are neither indexed nor listed.
+    let pages = wiki::docs::page_keys(&root).await.unwrap();
+    assert_eq!(pages, vec!["plans/roadmap".to_string()]);
+    let (_, index) = send(&mut service, "GET", "/", None).await;
+    assert!(!index.contains("leak"));
+    assert!(!index.contains("linked"));
+}